@@ -0,0 +1,53 @@
+/* C interface to the Intcode machine in the aoc2019 crate.
+ *
+ * Link against the crate's cdylib (libaoc2019.so / aoc2019.dll). */
+
+#ifndef INTCODE_H
+#define INTCODE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Results of intcode_run. */
+#define INTCODE_OUTPUT 0
+#define INTCODE_AWAITING_INPUT 1
+#define INTCODE_HALTED 2
+#define INTCODE_ERROR (-1)
+
+typedef struct IntcodeMachine IntcodeMachine;
+
+/* Creates a machine running a copy of len words. Returns NULL if words is
+ * NULL and len isn't 0. */
+IntcodeMachine *intcode_new(const int32_t *words, size_t len);
+
+/* Frees a machine. NULL is ignored. */
+void intcode_free(IntcodeMachine *machine);
+
+void intcode_push_input(IntcodeMachine *machine, int32_t value);
+
+/* Runs until the machine outputs a value, needs input, halts or fails,
+ * returning one of the INTCODE_ results above. */
+int intcode_run(IntcodeMachine *machine);
+
+/* Pops the oldest queued output into value, returning 0 if there was none. */
+int intcode_pop_output(IntcodeMachine *machine, int32_t *value);
+
+size_t intcode_memory_len(const IntcodeMachine *machine);
+
+/* Returns 0 if addr is out of bounds. */
+int intcode_read(const IntcodeMachine *machine, size_t addr, int32_t *value);
+int intcode_write(IntcodeMachine *machine, size_t addr, int32_t value);
+
+/* The message for the last INTCODE_ERROR, or NULL. Valid until the next
+ * intcode_run or intcode_free. */
+const char *intcode_error(const IntcodeMachine *machine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,77 @@
+use aoc2019::intcode_computer::bench;
+use aoc2019::intcode_computer::conformance;
+
+use std::fs;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "usage: intcode-bench [<corpus>...] [options]
+
+Checks each program in the corpus files (default benches/corpus/*.txt)
+against its expected output, then reports how many instructions per second
+the interpreter runs it at. Build with --release for meaningful numbers.
+
+options:
+    --time ms                run each program for at least this long
+                             (default 1000)";
+
+fn main() {
+    if let Err(e) = run(std::env::args().skip(1)) {
+        eprintln!("intcode-bench: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut time = Duration::from_millis(1000);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--time" => {
+                let ms = args.next().ok_or("--time needs a value")?;
+                time = Duration::from_millis(ms.parse().map_err(|e| format!("bad --time: {}", e))?);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.is_empty() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/corpus");
+        for entry in fs::read_dir(dir).map_err(|e| format!("can't read {}: {}", dir, e))? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                paths.push(path.display().to_string());
+            }
+        }
+        paths.sort();
+    }
+
+    let mut total_steps = 0;
+    let mut total_time = Duration::default();
+    for path in &paths {
+        let cases =
+            conformance::load_cases(path).map_err(|e| format!("can't load {}: {}", path, e))?;
+        for case in &cases {
+            let measurement = bench::measure(case, time)?;
+            println!("{}", measurement);
+            total_steps += measurement.steps;
+            total_time += measurement.elapsed;
+        }
+    }
+
+    println!(
+        "{:<16} {:>7}      {:>12} steps {:>9.2?} {:>8.1} M/s",
+        "total",
+        "",
+        total_steps,
+        total_time,
+        total_steps as f64 / total_time.as_secs_f64() / 1e6
+    );
+    Ok(())
+}
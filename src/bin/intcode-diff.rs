@@ -0,0 +1,112 @@
+use aoc2019::intcode_computer::{self, trace, Machine};
+
+use std::fs;
+use std::process;
+
+const USAGE: &str = "usage: intcode-diff <program> [<other program>] [options]
+
+Runs two machines in lock-step and reports the first step where they differ.
+With one program, both sides run it and differ only by their options.
+
+options:
+    --left-input 1,5         input for the left machine
+    --right-input 1,5        input for the right machine
+    --left-patch addr=value  patch the left program (repeatable)
+    --right-patch addr=value patch the right program (repeatable)
+    --context n              steps of context to show (default 5)
+    --max-steps n            give up after n steps (default 1000000)";
+
+#[derive(Default)]
+struct Side {
+    path: Option<String>,
+    input: Vec<i32>,
+    patches: Vec<(usize, i32)>,
+}
+
+fn main() {
+    if let Err(e) = run(std::env::args().skip(1)) {
+        eprintln!("intcode-diff: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut left = Side::default();
+    let mut right = Side::default();
+    let mut context = 5;
+    let mut max_steps = 1_000_000;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--left-input" | "--right-input" => {
+                let values = intcode_computer::parse_program(&value(&arg)?)
+                    .map_err(|e| format!("bad {}: {}", arg, e))?;
+                let side = if arg == "--left-input" {
+                    &mut left
+                } else {
+                    &mut right
+                };
+                side.input.extend(values);
+            }
+            "--left-patch" | "--right-patch" => {
+                let patch = intcode_computer::parse_patch(&value(&arg)?)?;
+                let side = if arg == "--left-patch" {
+                    &mut left
+                } else {
+                    &mut right
+                };
+                side.patches.push(patch);
+            }
+            "--context" => {
+                context = value(&arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --context: {}", e))?
+            }
+            "--max-steps" => {
+                max_steps = value(&arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --max-steps: {}", e))?
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if left.path.is_none() => left.path = Some(arg),
+            _ if right.path.is_none() => right.path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if right.path.is_none() {
+        right.path = left.path.clone();
+    }
+
+    match trace::diff(load(&left)?, load(&right)?, max_steps, context) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+        None => println!("no divergence"),
+    }
+
+    Ok(())
+}
+
+fn load(side: &Side) -> Result<Machine, String> {
+    let path = side.path.as_ref().ok_or("missing program path")?;
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut program = intcode_computer::parse_program(&text)
+        .map_err(|e| format!("can't parse {}: {}", path, e))?;
+
+    intcode_computer::apply_patches(&mut program, &side.patches)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut machine = Machine::new(program);
+    for &value in &side.input {
+        machine.push_input(value);
+    }
+    Ok(machine)
+}
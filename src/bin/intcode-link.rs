@@ -0,0 +1,54 @@
+use aoc2019::intcode_computer::link::{self, Module};
+
+use std::process;
+
+const USAGE: &str = "usage: intcode-link <module>... [options]
+
+Links relocatable modules into one program, loaded in the order given so the
+first module is the entry point, and prints it comma-separated.
+
+options:
+    --map                    print the address of every exported symbol to
+                             stderr";
+
+fn main() {
+    if let Err(e) = run(std::env::args().skip(1)) {
+        eprintln!("intcode-link: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    }
+}
+
+fn run(args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut map = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--map" => map = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return Err("no modules to link".to_string());
+    }
+
+    let modules = paths
+        .iter()
+        .map(|path| Module::load(path).map_err(|e| format!("can't load {}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let image = link::link(&modules)?;
+
+    if map {
+        for (name, addr) in &image.symbols {
+            eprintln!("{:>6} {}", addr, name);
+        }
+    }
+    let words: Vec<String> = image.program.iter().map(|v| v.to_string()).collect();
+    println!("{}", words.join(","));
+    Ok(())
+}
@@ -0,0 +1,292 @@
+use aoc2019::intcode_computer::watch::Watch;
+use aoc2019::intcode_computer::{self, Machine, State};
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "usage: intcode-watch <program> [options]
+
+Shows a machine full-screen as it runs.
+
+keys:
+    s or space               run one instruction
+    r                        run until paused, halted or out of input
+    p                        pause
+    < >                      halve or double the running speed
+    0-9 and -                type an input value; enter queues it
+    q                        quit
+
+options:
+    --input 1,5              queue comma-separated input values
+    --patch addr=value       set a memory cell before running (repeatable)
+    --speed n                instructions per second while running
+                             (default 50, at most 1000000)";
+
+const FRAME: Duration = Duration::from_millis(33);
+
+/// The fastest the machine can be run, in instructions per second.
+const MAX_SPEED: usize = 1_000_000;
+
+fn main() {
+    let (machine, speed) = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("intcode-watch: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(Watch::new(machine), speed) {
+        eprintln!("intcode-watch: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Machine, usize), String> {
+    let mut path = None;
+    let mut input = Vec::new();
+    let mut patches = Vec::new();
+    let mut speed = 50;
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--input" => input.extend(
+                intcode_computer::parse_program(&value(&arg)?)
+                    .map_err(|e| format!("bad --input: {}", e))?,
+            ),
+            "--patch" => patches.push(intcode_computer::parse_patch(&value(&arg)?)?),
+            "--speed" => {
+                speed = value(&arg)?
+                    .parse()
+                    .map_err(|e| format!("bad --speed: {}", e))?
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let path = path.ok_or("missing program path")?;
+    let text = fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    let mut program = intcode_computer::parse_program(&text)
+        .map_err(|e| format!("can't parse {}: {}", path, e))?;
+    intcode_computer::apply_patches(&mut program, &patches)
+        .map_err(|e| format!("{}: {}", path, e))?;
+
+    let mut machine = Machine::new(program);
+    for value in input {
+        machine.push_input(value);
+    }
+    Ok((machine, speed.clamp(1, MAX_SPEED)))
+}
+
+/// Set by the `SIGWINCH` handler, so the size is only asked for again after
+/// the terminal is resized.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+/// Has `RESIZED` set whenever the terminal is resized. Without the `libc`
+/// crate there's no portable name for `SIGWINCH`, so this is only done where
+/// it's known to be 28; elsewhere the size is read once at startup.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn watch_resizes() {
+    use std::os::raw::c_int;
+
+    const SIGWINCH: c_int = 28;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn on_resize(_: c_int) {
+        RESIZED.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic.
+    unsafe { signal(SIGWINCH, on_resize) };
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn watch_resizes() {}
+
+/// Puts the terminal in raw mode on the alternate screen, restoring it when
+/// dropped.
+struct Terminal {
+    saved: String,
+    size: (usize, usize),
+}
+
+impl Terminal {
+    fn raw() -> Result<Self, String> {
+        let saved = stty(&["-g"]).map_err(|e| format!("stdin isn't a terminal: {}", e))?;
+        stty(&["raw", "-echo"])?;
+        print!("\x1b[?1049h\x1b[?25l");
+        watch_resizes();
+        Ok(Terminal {
+            saved,
+            size: query_size(),
+        })
+    }
+
+    /// Whether the terminal has been resized since `size` last looked.
+    fn resized(&self) -> bool {
+        RESIZED.load(Ordering::Relaxed)
+    }
+
+    /// `(width, height)`, assuming 80x24 if the terminal won't say.
+    fn size(&mut self) -> (usize, usize) {
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            self.size = query_size();
+        }
+        self.size
+    }
+}
+
+fn query_size() -> (usize, usize) {
+    let size = stty(&["size"]).unwrap_or_default();
+    let mut parts = size.split_whitespace().map(str::parse::<usize>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(rows)), Some(Ok(columns))) if rows > 0 && columns > 0 => (columns, rows),
+        _ => (80, 24),
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        io::stdout().flush().ok();
+        stty(&[self.saved.as_str()]).ok();
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("can't run stty: {}", e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn run(mut watch: Watch, mut speed: usize) -> Result<(), String> {
+    let mut terminal = Terminal::raw()?;
+
+    let (sender, keys) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 16];
+        while let Ok(n @ 1..) = io::stdin().read(&mut buffer) {
+            if buffer[..n].iter().any(|&byte| sender.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+
+    let mut running = false;
+    let mut typed = String::new();
+    // Instructions owed while running, carried between frames so that
+    // speeds below the frame rate still make progress.
+    let mut budget = 0.0;
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            draw(&watch, terminal.size(), speed, running, &typed)?;
+        }
+        redraw = true;
+
+        // While paused, wake up each frame only to notice a resize.
+        let key = match keys.recv_timeout(FRAME) {
+            Ok(key) => Some(key),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+
+        match key {
+            // Ctrl-C arrives as a byte in raw mode.
+            Some(b'q') | Some(3) => return Ok(()),
+            Some(b's') | Some(b' ') => {
+                running = false;
+                watch.step().ok();
+            }
+            Some(b'r') => {
+                running = true;
+                budget = 0.0;
+            }
+            Some(b'p') => running = false,
+            Some(b'>') => speed = (speed * 2).min(MAX_SPEED),
+            Some(b'<') => speed = (speed / 2).max(1),
+            Some(c) if c.is_ascii_digit() || (c == b'-' && typed.is_empty()) => {
+                typed.push(c as char)
+            }
+            Some(8) | Some(127) => {
+                typed.pop();
+            }
+            Some(b'\r') | Some(b'\n') => {
+                if let Ok(value) = typed.parse() {
+                    watch.machine.push_input(value);
+                    typed.clear();
+                }
+            }
+            Some(_) => {}
+            None if !running => redraw = terminal.resized(),
+            None => {
+                budget += speed as f64 * FRAME.as_secs_f64();
+                let steps = budget as usize;
+                budget -= steps as f64;
+                if watch.run(steps) != Ok(State::Running) {
+                    running = false;
+                }
+            }
+        }
+    }
+}
+
+fn draw(
+    watch: &Watch,
+    (width, height): (usize, usize),
+    speed: usize,
+    running: bool,
+    typed: &str,
+) -> Result<(), String> {
+    let status = format!(
+        "{} at {}/s  input: {}_  [s]tep [r]un [p]ause [<>] speed [q]uit",
+        if running { "running" } else { "paused" },
+        speed,
+        typed
+    );
+    let mut out = io::stdout();
+    write!(out, "{}", watch.frame(width, height, &status))
+        .and_then(|()| out.flush())
+        .map_err(|e| e.to_string())
+}
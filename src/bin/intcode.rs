@@ -0,0 +1,362 @@
+use aoc2019::intcode_computer::debugger::Debugger;
+use aoc2019::intcode_computer::session::{self, Recorder, Session};
+use aoc2019::intcode_computer::{self, InstructionSet, Machine, Overflow, State};
+use aoc2019::intcode_computer::{decompile, server, taint, transpile};
+
+use std::fs;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::process;
+
+const USAGE: &str = "usage: intcode <program> [options]
+
+options:
+    --input 1,5              queue comma-separated input values
+    --stdin                  read input values from stdin
+    --ascii                  interactive ASCII mode: stdin lines become input,
+                             outputs below 128 are printed as characters
+    --patch addr=value       set a memory cell before running (repeatable)
+    --dump                   print final memory after the program halts
+    --trace                  print each instruction to stderr before it runs
+    --max-steps n            stop after n instructions
+    --record file            save every input and output to a session file
+    --replay file            feed a recorded session's inputs back and check
+                             that the outputs match
+    --transpile name         print the program as a compiled Rust function
+                             instead of running it
+    --decompile              print the program as structured pseudo-code
+                             instead of running it
+    --entry addr             where --decompile starts reading code
+                             (repeatable, default 0)
+    --taint                  report which inputs influenced each output and
+                             conditional jump instead of printing outputs
+    --listen addr            serve the debug protocol on unix:path or a
+                             localhost host:port instead of running
+    --strict                 reject malformed parameter modes
+    --overflow policy        wrapping, checked (default) or saturating
+    --instruction-set set    basic (day 2), day5 or full (default); anything
+                             beyond the set is an error";
+
+struct Options {
+    path: String,
+    input: Vec<i32>,
+    stdin: bool,
+    ascii: bool,
+    patches: Vec<(usize, i32)>,
+    dump: bool,
+    trace: bool,
+    max_steps: Option<usize>,
+    record: Option<String>,
+    replay: Option<String>,
+    transpile: Option<String>,
+    decompile: bool,
+    entries: Vec<usize>,
+    taint: bool,
+    listen: Option<String>,
+    strict: bool,
+    overflow: Overflow,
+    instruction_set: InstructionSet,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("intcode: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut options = Options {
+        path: String::new(),
+        input: Vec::new(),
+        stdin: false,
+        ascii: false,
+        patches: Vec::new(),
+        dump: false,
+        trace: false,
+        max_steps: None,
+        record: None,
+        replay: None,
+        transpile: None,
+        decompile: false,
+        entries: Vec::new(),
+        taint: false,
+        listen: None,
+        strict: false,
+        overflow: Overflow::Checked,
+        instruction_set: InstructionSet::Full,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--input" => {
+                let values = value("--input")?;
+                options.input.extend(
+                    intcode_computer::parse_program(&values)
+                        .map_err(|e| format!("bad --input: {}", e))?,
+                );
+            }
+            "--stdin" => options.stdin = true,
+            "--ascii" => options.ascii = true,
+            "--patch" => {
+                let patch = value("--patch")?;
+                options.patches.push(intcode_computer::parse_patch(&patch)?);
+            }
+            "--dump" => options.dump = true,
+            "--trace" => options.trace = true,
+            "--max-steps" => {
+                let steps = value("--max-steps")?;
+                options.max_steps = Some(
+                    steps
+                        .parse()
+                        .map_err(|e| format!("bad --max-steps: {}", e))?,
+                );
+            }
+            "--record" => options.record = Some(value("--record")?),
+            "--replay" => options.replay = Some(value("--replay")?),
+            "--transpile" => options.transpile = Some(value("--transpile")?),
+            "--decompile" => options.decompile = true,
+            "--entry" => {
+                let entry = value("--entry")?;
+                options
+                    .entries
+                    .push(entry.parse().map_err(|e| format!("bad --entry: {}", e))?);
+            }
+            "--taint" => options.taint = true,
+            "--listen" => options.listen = Some(value("--listen")?),
+            "--strict" => options.strict = true,
+            "--overflow" => {
+                options.overflow = match value("--overflow")?.as_str() {
+                    "wrapping" => Overflow::Wrapping,
+                    "checked" => Overflow::Checked,
+                    "saturating" => Overflow::Saturating,
+                    other => return Err(format!("unknown overflow policy {}", other)),
+                };
+            }
+            "--instruction-set" => {
+                options.instruction_set = value("--instruction-set")?.parse()?;
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if options.stdin && options.ascii {
+        return Err("--stdin and --ascii both read stdin".to_string());
+    }
+
+    if options.replay.is_some() && (options.stdin || options.ascii || !options.input.is_empty()) {
+        return Err("--replay takes its input from the session".to_string());
+    }
+
+    if options.taint && (options.ascii || options.record.is_some() || options.replay.is_some()) {
+        return Err("--taint can't be combined with --ascii, --record or --replay".to_string());
+    }
+
+    if options.listen.is_some()
+        && (options.taint || options.ascii || options.record.is_some() || options.replay.is_some())
+    {
+        return Err(
+            "--listen can't be combined with --taint, --ascii, --record or --replay".to_string(),
+        );
+    }
+
+    if (options.trace || options.max_steps.is_some())
+        && (options.taint || options.replay.is_some() || options.listen.is_some())
+    {
+        return Err(
+            "--trace and --max-steps can't be combined with --taint, --replay or --listen"
+                .to_string(),
+        );
+    }
+
+    if options.transpile.is_some() && options.decompile {
+        return Err("--transpile and --decompile both print the program".to_string());
+    }
+
+    if (options.transpile.is_some() || options.decompile)
+        && (!options.input.is_empty()
+            || options.stdin
+            || options.ascii
+            || options.dump
+            || options.trace
+            || options.max_steps.is_some()
+            || options.record.is_some()
+            || options.replay.is_some()
+            || options.taint
+            || options.listen.is_some())
+    {
+        return Err(
+            "--transpile and --decompile don't run the program, so they can't be \
+                    combined with --input, --stdin, --ascii, --dump, --trace, --max-steps, \
+                    --record, --replay, --taint or --listen"
+                .to_string(),
+        );
+    }
+
+    if !options.entries.is_empty() && !options.decompile {
+        return Err("--entry only applies to --decompile".to_string());
+    }
+    if options.entries.is_empty() {
+        options.entries.push(0);
+    }
+
+    options.path = path.ok_or("missing program path")?;
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let text = fs::read_to_string(&options.path)
+        .map_err(|e| format!("can't read {}: {}", options.path, e))?;
+    let mut program = intcode_computer::parse_program(&text)
+        .map_err(|e| format!("can't parse {}: {}", options.path, e))?;
+
+    intcode_computer::apply_patches(&mut program, &options.patches)?;
+
+    if let Some(name) = &options.transpile {
+        println!("use aoc2019::intcode_computer::transpile::Exit;");
+        println!("use std::collections::VecDeque;\n");
+        print!("{}", transpile::transpile(&program, name));
+        return Ok(());
+    }
+
+    if options.decompile {
+        if let Some(&entry) = options
+            .entries
+            .iter()
+            .find(|&&entry| entry >= program.len())
+        {
+            return Err(format!("entry {} is outside the program", entry));
+        }
+        print!("{}", decompile::decompile(&program, &options.entries));
+        return Ok(());
+    }
+
+    let mut machine = Machine::new(program)
+        .strict(options.strict)
+        .overflow(options.overflow)
+        .instruction_set(options.instruction_set);
+
+    for &value in &options.input {
+        machine.push_input(value);
+    }
+    if options.stdin {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("can't read stdin: {}", e))?;
+        for value in text.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !value.is_empty() {
+                machine.push_input(
+                    value
+                        .parse()
+                        .map_err(|e| format!("bad stdin input {}: {}", value, e))?,
+                );
+            }
+        }
+    }
+
+    if let Some(addr) = &options.listen {
+        let listener = server::Listener::bind(addr)?;
+        eprintln!("listening on {}", listener.local_addr());
+        let mut debugger = Debugger::new(machine);
+        listener.run(&mut debugger)?;
+        machine = debugger.machine;
+    } else if options.taint {
+        let report = taint::analyze(&mut machine)?;
+        println!("{}", report);
+    } else if let Some(path) = &options.replay {
+        let session = Session::load(path).map_err(|e| format!("can't load {}: {}", path, e))?;
+        session::replay(&mut machine, &session).map_err(|e| e.to_string())?;
+        eprintln!("replay matched {} events", session.entries.len());
+    } else {
+        let mut recorder = options.record.as_ref().map(|_| Recorder::new());
+        let result = execute(&mut machine, &options, &mut recorder);
+
+        if let (Some(path), Some(recorder)) = (&options.record, recorder) {
+            recorder
+                .finish()
+                .save(path)
+                .map_err(|e| format!("can't save {}: {}", path, e))?;
+        }
+        result?;
+    }
+
+    if options.dump {
+        let memory: Vec<String> = machine.memory().iter().map(|v| v.to_string()).collect();
+        println!("{}", memory.join(","));
+    }
+
+    Ok(())
+}
+
+fn execute(
+    machine: &mut Machine,
+    options: &Options,
+    recorder: &mut Option<Recorder>,
+) -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    loop {
+        let steps = machine.steps();
+        if options.max_steps == Some(steps) {
+            return Err(format!("step limit of {} reached", steps));
+        }
+        if options.trace {
+            let (text, _) = machine.memory().disassemble(machine.instr_ptr());
+            eprintln!("{:>6} {:>5}: {}", steps, machine.instr_ptr(), text);
+        }
+
+        let state = match recorder {
+            Some(recorder) => recorder.step(machine),
+            None => machine.step(),
+        }
+        .map_err(|e| format!("{} after {} steps", e, steps))?;
+
+        for value in machine.take_output() {
+            if options.ascii && (0..128).contains(&value) {
+                write!(out, "{}", value as u8 as char)
+            } else {
+                writeln!(out, "{}", value)
+            }
+            .map_err(|e| e.to_string())?;
+        }
+
+        match state {
+            State::Running => {}
+            State::AwaitingInput if options.ascii => {
+                out.flush().map_err(|e| e.to_string())?;
+                let line = match lines.next() {
+                    Some(line) => line.map_err(|e| format!("can't read stdin: {}", e))?,
+                    None => return Err("program wants input but stdin is closed".to_string()),
+                };
+                for c in line.chars().chain(Some('\n')) {
+                    machine.push_input(c as i32);
+                }
+            }
+            State::AwaitingInput => {
+                return Err(format!("program ran out of input after {} steps", steps))
+            }
+            State::Halted => return Ok(()),
+        }
+    }
+}
@@ -1,50 +1,54 @@
 use crate::utils;
 use crate::utils::Part;
 
-use crate::intcode_computer;
-
-use std::collections::VecDeque;
+use crate::intcode_computer::search::Search;
+use crate::intcode_computer::symbolic;
+use crate::intcode_computer::{Machine, Program};
 
 pub fn solve(part: Part) -> i32 {
     let mut input = String::new();
     utils::read_input_to_string(&mut input, 2).unwrap();
 
-    let mut program: Vec<i32> = input
-        .trim()
-        .split(",")
-        .map(|s| s.trim().parse::<i32>().unwrap())
-        .collect();
+    let program = Program::parse(&input).unwrap();
 
     match part {
         Part::One => {
-            program[1] = 12; //noun
-            program[2] = 2; //verb
-
-            intcode_computer::run_program(&mut program, VecDeque::new());
-
-            program[0]
+            let mut machine = Machine::new(&program);
+            machine
+                .reset_with(&[
+                    (1, 12), //noun
+                    (2, 2),  //verb
+                ])
+                .unwrap();
+            machine.run().unwrap();
+
+            machine.memory()[0]
         }
         Part::Two => {
-            let mut noun = 0;
-            let mut verb = 0;
-            'outer: for i in 0..100 {
-                for j in 0..100 {
-                    let mut test_program = program.clone();
-
-                    test_program[1] = i;
-                    test_program[2] = j;
-
-                    intcode_computer::run_program(&mut test_program, VecDeque::new());
-
-                    if test_program[0] == 19690720 {
-                        noun = i;
-                        verb = j;
-                        break 'outer;
+            // memory[0] is usually an affine function of the noun and verb,
+            // in which case it can be solved for directly. The analysis works
+            // in i64 rather than the machine's checked i32, so the answer is
+            // only trusted once the machine agrees.
+            let analysis = symbolic::analyze(&program.to_vec(), &[1, 2], &[], 100_000);
+            if let Ok(Ok(expr)) = analysis.map(|a| a.memory[0].clone()) {
+                if let Some(values) = symbolic::solve(&expr, 19690720, &[0..100, 0..100]) {
+                    let (noun, verb) = (values[0] as i32, values[1] as i32);
+                    let mut machine = Machine::new(&program);
+                    machine.reset_with(&[(1, noun), (2, verb)]).unwrap();
+                    if machine.run().is_ok() && machine.memory()[0] == 19690720 {
+                        return 100 * noun + verb;
                     }
                 }
             }
 
-            100 * noun + verb
+            let found = Search::new(&program)
+                .patch(1, 0..100) //noun
+                .patch(2, 0..100) //verb
+                .first(|machine| machine.memory()[0] == 19690720)
+                .unwrap()
+                .unwrap();
+
+            100 * found.values[0] + found.values[1]
         }
     }
 }
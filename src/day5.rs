@@ -1,33 +1,30 @@
 use crate::utils;
 use crate::utils::Part;
 
-use crate::intcode_computer;
-
-use std::collections::VecDeque;
+use crate::intcode_computer::diagnostic;
+use crate::intcode_computer::Machine;
 
 pub fn solve(part: Part) -> i32 {
     let mut input = String::new();
     utils::read_input_to_string(&mut input, 5).unwrap();
 
-    let mut program: Vec<i32> = input
+    let program: Vec<i32> = input
         .trim()
         .split(",")
         .map(|s| s.trim().parse::<i32>().unwrap())
         .collect();
 
-    let mut input = VecDeque::new();
+    let mut machine = Machine::new(program);
     match part {
         Part::One => {
-            input.push_front(1);
+            machine.push_input(1);
         },
         Part::Two => {
-            input.push_front(5);
+            machine.push_input(5);
         }
     }
-    
-
-    let output = intcode_computer::run_program(&mut program, input);
 
-    println!("Output: {:?}", output);
-    *output.last().unwrap()
+    let report = diagnostic::diagnose(&mut machine).unwrap();
+    assert!(report.passed(), "{}", report);
+    report.diagnostic_code()
 }
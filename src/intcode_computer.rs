@@ -1,140 +1,764 @@
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::ParseIntError;
 
-pub fn run_program(program: &mut Vec<i32>, mut input: VecDeque<i32>) -> Vec<i32> {
-    let mut output = Vec::new();
+pub mod bench;
+pub mod conformance;
+pub mod debugger;
+pub mod decompile;
+pub mod device;
+pub mod diagnostic;
+pub mod explore;
+pub mod expr;
+pub mod ffi;
+pub mod flow;
+pub mod hooks;
+pub mod link;
+pub mod memory;
+pub mod render;
+pub mod search;
+pub mod server;
+pub mod session;
+pub mod symbolic;
+pub mod taint;
+pub mod trace;
+pub mod transpile;
+pub mod watch;
 
-    let mut instr_ptr = 0;
+use device::Device;
+use hooks::{Control, Event, Hooks, Stop};
+use memory::Memory;
+use transpile::{Compiled, Exit};
 
-    'eval: loop {
-        let instr: Instruction = program[instr_ptr].into();
+/// Runs `program` to completion in place, leaving its final memory behind.
+/// To run a program more than once, build a `Machine` from a `Program`
+/// instead and `reset` it.
+pub fn run_program(program: &mut Vec<i32>, input: VecDeque<i32>) -> Vec<i32> {
+    let mut machine = Machine::new(std::mem::take(program));
+    machine.input = input;
 
-        match instr.op {
-            OpCode::Halt => {
-                break 'eval;
+    match machine.run() {
+        Ok(State::Halted) => {}
+        Ok(_) => panic!("Program requested more input than was provided"),
+        Err(e) => panic!("{}", e),
+    }
+
+    let output = machine.take_output();
+    *program = machine.into_memory();
+    output
+}
+
+pub fn parse_program(text: &str) -> Result<Vec<i32>, ParseIntError> {
+    text.trim()
+        .split(',')
+        .map(|s| s.trim().parse::<i32>())
+        .collect()
+}
+
+/// Parses an `addr=value` memory patch.
+pub fn parse_patch(patch: &str) -> Result<(usize, i32), String> {
+    let mut parts = patch.splitn(2, '=');
+    let addr = parts.next().unwrap().trim().parse::<usize>();
+    let value = parts.next().map(|v| v.trim().parse::<i32>());
+
+    match (addr, value) {
+        (Ok(addr), Some(Ok(value))) => Ok((addr, value)),
+        _ => Err(format!("bad patch {}, expected addr=value", patch)),
+    }
+}
+
+/// Sets each `(addr, value)` in `program`. If any address is outside it,
+/// nothing is changed and the error names the first one.
+pub fn apply_patches(program: &mut [i32], patches: &[(usize, i32)]) -> Result<(), String> {
+    check_patches(patches.iter().map(|&(addr, _)| addr), program.len())?;
+    for &(addr, value) in patches {
+        program[addr] = value;
+    }
+    Ok(())
+}
+
+/// Checks that patch addresses are inside a program of `len` words.
+fn check_patches<I: IntoIterator<Item = usize>>(addrs: I, len: usize) -> Result<(), String> {
+    match addrs.into_iter().find(|&addr| addr >= len) {
+        Some(addr) => Err(format!(
+            "patch address {} is outside the program ({} words)",
+            addr, len
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Renders the instruction at `ptr` as text, returning it with its length in words.
+///
+/// Position mode operands are shown as `[addr]` and relative mode ones as
+/// `[rb+offset]`. Words that don't decode are shown as a single `data` word,
+/// and a `ptr` past the end as `<out of bounds>` with a length of 0.
+pub fn disassemble(memory: &[i32], ptr: usize) -> (String, usize) {
+    let word = match memory.get(ptr) {
+        Some(&word) => word,
+        None => return ("<out of bounds>".to_string(), 0),
+    };
+    let instr = match Instruction::decode(word, false) {
+        Ok(instr) if ptr + OpCode::value_count(instr.op) <= memory.len() => instr,
+        _ => return (format!("data {}", word), 1),
+    };
+
+    let operands: Vec<String> = instr
+        .modes
+        .iter()
+        .enumerate()
+        .map(|(i, mode)| match mode {
+            ParameterMode::Position => format!("[{}]", memory[ptr + 1 + i]),
+            ParameterMode::Immediate => memory[ptr + 1 + i].to_string(),
+            ParameterMode::Relative => format!("[rb{:+}]", memory[ptr + 1 + i]),
+        })
+        .collect();
+
+    let text = if operands.is_empty() {
+        OpCode::mnemonic(instr.op).to_string()
+    } else {
+        format!("{} {}", OpCode::mnemonic(instr.op), operands.join(", "))
+    };
+    (text, OpCode::value_count(instr.op))
+}
+
+/// Reports every instruction in `program` that would be rejected in strict mode
+/// by a machine limited to `set`.
+///
+/// The scan is linear from address 0, so data words mixed in with the code are
+/// reported too. An unknown opcode is skipped one word at a time.
+pub fn validate(program: &[i32], set: InstructionSet) -> Vec<Error> {
+    let mut errors = Vec::new();
+
+    let mut ptr = 0;
+    while ptr < program.len() {
+        let decoded = Instruction::decode(program[ptr], true)
+            .and_then(|instr| instr.check(set).map(|()| instr));
+        match decoded {
+            Ok(instr) => ptr += OpCode::value_count(instr.op),
+            Err(kind) => {
+                errors.push(Error { ip: ptr, kind });
+                ptr += match kind {
+                    ErrorKind::UnknownOpCode(_) => 1,
+                    _ => OpCode::from_code(program[ptr] % 100).map_or(1, OpCode::value_count),
+                };
             }
-            OpCode::Add => {
-                let result_ptr = program[instr_ptr + 3] as usize;
+        }
+    }
+
+    errors
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum State {
+    Running,
+    AwaitingInput,
+    Halted,
+}
+
+/// The instructions and parameter modes a machine accepts, by the puzzle that
+/// introduced them. Each level includes the ones before it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InstructionSet {
+    /// Day 2: Add, Mul and Halt in position mode.
+    Basic,
+    /// Day 5: adds input, output, jumps, comparisons and immediate mode.
+    Day5,
+    /// Day 9: adds relative mode and adjusting the relative base.
+    Full,
+}
+
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InstructionSet::Basic => "basic",
+            InstructionSet::Day5 => "day5",
+            InstructionSet::Full => "full",
+        })
+    }
+}
+
+impl std::str::FromStr for InstructionSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "basic" => Ok(InstructionSet::Basic),
+            "day5" => Ok(InstructionSet::Day5),
+            "full" => Ok(InstructionSet::Full),
+            _ => Err(format!("unknown instruction set {}", s)),
+        }
+    }
+}
+
+/// What Add and Mul do when the result doesn't fit in an `i32`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Overflow {
+    Wrapping,
+    Checked,
+    Saturating,
+}
+
+impl Overflow {
+    fn add(self, a: i32, b: i32) -> Option<i32> {
+        match self {
+            Overflow::Wrapping => Some(a.wrapping_add(b)),
+            Overflow::Checked => a.checked_add(b),
+            Overflow::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    fn mul(self, a: i32, b: i32) -> Option<i32> {
+        match self {
+            Overflow::Wrapping => Some(a.wrapping_mul(b)),
+            Overflow::Checked => a.checked_mul(b),
+            Overflow::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+/// An immutable program image. Clones share the same pages, as do the
+/// machines built from it until they write to them, so one program can back
+/// any number of machines across threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    memory: Memory,
+}
+
+impl Program {
+    pub fn parse(text: &str) -> Result<Program, ParseIntError> {
+        parse_program(text).map(Program::from)
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty()
+    }
+
+    pub fn to_vec(&self) -> Vec<i32> {
+        self.memory.to_vec()
+    }
+}
+
+impl From<Vec<i32>> for Program {
+    fn from(words: Vec<i32>) -> Self {
+        Program {
+            memory: words.into(),
+        }
+    }
+}
+
+impl From<&[i32]> for Program {
+    fn from(words: &[i32]) -> Self {
+        Program::from(words.to_vec())
+    }
+}
+
+impl<const N: usize> From<&[i32; N]> for Program {
+    fn from(words: &[i32; N]) -> Self {
+        Program::from(&words[..])
+    }
+}
+
+impl From<&Program> for Program {
+    fn from(program: &Program) -> Self {
+        program.clone()
+    }
+}
+
+/// Cloning a machine is cheap: clones share memory until they write to it.
+#[derive(Debug, Clone)]
+pub struct Machine {
+    program: Program,
+    memory: Memory,
+    instr_ptr: usize,
+    relative_base: i32,
+    steps: usize,
+    input: VecDeque<i32>,
+    output: Vec<i32>,
+    strict: bool,
+    overflow: Overflow,
+    instruction_set: InstructionSet,
+    fallen_back: bool,
+}
+
+impl Machine {
+    /// Takes a `&Program` to share its image, or anything that converts into
+    /// one, like a `Vec<i32>`.
+    pub fn new<P: Into<Program>>(program: P) -> Self {
+        let program = program.into();
+        Machine {
+            memory: program.memory.clone(),
+            program,
+            instr_ptr: 0,
+            relative_base: 0,
+            steps: 0,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            strict: false,
+            overflow: Overflow::Checked,
+            instruction_set: InstructionSet::Full,
+            fallen_back: false,
+        }
+    }
+
+    /// In strict mode, immediate mode on a write parameter and mode digits beyond
+    /// an instruction's arity are errors instead of being ignored.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Defaults to `Overflow::Checked`, which stops the machine with an error.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Defaults to `InstructionSet::Full`. Anything beyond the set stops the
+    /// machine with an error.
+    pub fn instruction_set(mut self, set: InstructionSet) -> Self {
+        self.instruction_set = set;
+        self
+    }
+
+    /// Puts the machine back to how `Machine::new` left it, with its
+    /// program's memory and no input or output, keeping its settings.
+    pub fn reset(&mut self) {
+        self.memory = self.program.memory.clone();
+        self.instr_ptr = 0;
+        self.relative_base = 0;
+        self.steps = 0;
+        self.input.clear();
+        self.output.clear();
+        self.fallen_back = false;
+    }
+
+    /// Resets, then sets each `(addr, value)` in memory. If an address is
+    /// outside the program, the machine is left as it was and the error names
+    /// it, as `apply_patches` would.
+    pub fn reset_with(&mut self, patches: &[(usize, i32)]) -> Result<(), String> {
+        check_patches(patches.iter().map(|&(addr, _)| addr), self.program.len())?;
+        self.reset();
+        for &(addr, value) in patches {
+            self.memory[addr] = value;
+        }
+        Ok(())
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn push_input(&mut self, value: i32) {
+        self.input.push_back(value);
+    }
+
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    pub fn take_output(&mut self) -> Vec<i32> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn into_memory(self) -> Vec<i32> {
+        self.memory.to_vec()
+    }
 
-                program[result_ptr] = get_operand(instr_ptr + 1, instr.modes[0], program)
-                    + get_operand(instr_ptr + 2, instr.modes[1], program);
+    pub fn instr_ptr(&self) -> usize {
+        self.instr_ptr
+    }
+
+    pub fn relative_base(&self) -> i32 {
+        self.relative_base
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
 
-                instr_ptr += OpCode::value_count(instr.op);
+    pub fn run(&mut self) -> Result<State, Error> {
+        loop {
+            match self.step()? {
+                State::Running => {}
+                state => return Ok(state),
             }
-            OpCode::Mul => {
-                let result_ptr = program[instr_ptr + 3] as usize;
+        }
+    }
 
-                program[result_ptr] = get_operand(instr_ptr + 1, instr.modes[0], program)
-                    * get_operand(instr_ptr + 2, instr.modes[1], program);
+    /// Runs with `device` attached: every output word is handed to it, and it
+    /// supplies input whenever the input queue is empty. Returns
+    /// `State::AwaitingInput` if the device has nothing to give.
+    pub fn run_with<D: Device + ?Sized>(&mut self, device: &mut D) -> Result<State, Error> {
+        loop {
+            let state = self.step()?;
+            for value in self.output.drain(..) {
+                device.output(value);
+            }
 
-                instr_ptr += OpCode::value_count(instr.op);
+            match state {
+                State::Running => {}
+                State::AwaitingInput => match device.input() {
+                    Some(value) => self.input.push_back(value),
+                    None => return Ok(State::AwaitingInput),
+                },
+                State::Halted => return Ok(State::Halted),
             }
-            OpCode::Input => {
-                let result_ptr = program[instr_ptr + 1] as usize;
-                program[result_ptr] = input.pop_front().unwrap();
+        }
+    }
+
+    /// Runs code generated by `transpile::transpile` for this machine's program,
+    /// handing over to the interpreter for good the first time it falls back.
+    /// Instructions run by compiled code aren't counted in `steps`. Strict
+    /// machines and machines limited to less than the full instruction set
+    /// always interpret, since compiled code doesn't check modes or the level.
+    pub fn run_compiled(&mut self, compiled: Compiled) -> Result<State, Error> {
+        if self.fallen_back || self.strict || self.instruction_set != InstructionSet::Full {
+            return self.run();
+        }
 
-                instr_ptr += OpCode::value_count(instr.op);
+        let mut memory = self.memory.to_vec();
+        let exit = compiled(
+            &mut memory,
+            &mut self.input,
+            &mut self.output,
+            self.instr_ptr,
+        );
+        self.memory = memory.into();
+
+        match exit {
+            Exit::Halted(ip) => {
+                self.instr_ptr = ip;
+                Ok(State::Halted)
             }
-            OpCode::Output => {
-                output.push(get_operand(instr_ptr + 1, instr.modes[0], program));
+            Exit::AwaitingInput(ip) => {
+                self.instr_ptr = ip;
+                Ok(State::AwaitingInput)
+            }
+            Exit::Fallback(ip) => {
+                self.instr_ptr = ip;
+                self.fallen_back = true;
+                self.run()
+            }
+        }
+    }
 
-                instr_ptr += OpCode::value_count(instr.op);
+    /// Runs until the machine stops or a callback in `hooks` aborts.
+    pub fn run_hooked(&mut self, hooks: &mut Hooks) -> Result<Stop, Error> {
+        loop {
+            match self.step_hooked(hooks)? {
+                Stop::Machine(State::Running) => {}
+                stop => return Ok(stop),
             }
-            OpCode::JumpIfTrue => {
-                if get_operand(instr_ptr + 1, instr.modes[0], program) != 0 {
-                    instr_ptr = get_operand(instr_ptr + 2, instr.modes[1], program) as usize;
-                } else {
-                    instr_ptr += OpCode::value_count(instr.op);
+        }
+    }
+
+    /// Runs one instruction, calling `hooks` for everything it does.
+    pub fn step_hooked(&mut self, hooks: &mut Hooks) -> Result<Stop, Error> {
+        self.execute(&mut |machine, event| hooks.fire(machine, event))
+    }
+
+    pub fn step(&mut self) -> Result<State, Error> {
+        match self.execute(&mut |_, _| Control::Continue)? {
+            Stop::Machine(state) => Ok(state),
+            Stop::Aborted(_) => unreachable!("nothing can abort an unhooked step"),
+        }
+    }
+
+    /// Decodes the instruction at the ip and resolves its parameters against
+    /// the current memory, without running it.
+    fn decode(&self) -> Result<Decoded, Error> {
+        let ip = self.instr_ptr;
+        let error = |kind| Error { ip, kind };
+        let instr = load(&self.memory, ip)
+            .and_then(|word| Instruction::decode(word, self.strict))
+            .and_then(|instr| instr.check(self.instruction_set).map(|()| instr))
+            .map_err(error)?;
+        let base = self.relative_base;
+
+        let mut reads = [(0, None); 2];
+        let mut read_count = 0;
+        let mut write = None;
+        for (i, &role) in OpCode::info(instr.op).params.iter().enumerate() {
+            let ptr = ip + 1 + i;
+            match (role, instr.modes[i]) {
+                (Role::Read, ParameterMode::Immediate) => {
+                    reads[read_count] = (load(&self.memory, ptr).map_err(error)?, None);
+                    read_count += 1;
+                }
+                (Role::Read, mode) => {
+                    let addr = address(ptr, mode, &self.memory, base).map_err(error)?;
+                    reads[read_count] = (self.memory[addr], Some(addr));
+                    read_count += 1;
+                }
+                (Role::Write, mode) => {
+                    write = Some(address(ptr, mode, &self.memory, base).map_err(error)?)
                 }
             }
-            OpCode::JumpIfFalse => {
-                if get_operand(instr_ptr + 1, instr.modes[0], program) == 0 {
-                    instr_ptr = get_operand(instr_ptr + 2, instr.modes[1], program) as usize;
-                } else {
-                    instr_ptr += OpCode::value_count(instr.op);
+        }
+        Ok(Decoded {
+            instr,
+            reads,
+            read_count,
+            write,
+        })
+    }
+
+    /// Runs one instruction, reporting each event to `hook` before making the
+    /// change it describes, so an abort leaves the machine untouched.
+    fn execute<H>(&mut self, hook: &mut H) -> Result<Stop, Error>
+    where
+        H: FnMut(&Machine, Event) -> Control,
+    {
+        macro_rules! fire {
+            ($event:expr) => {
+                let event = $event;
+                if hook(self, event) == Control::Abort {
+                    return Ok(Stop::Aborted(event));
                 }
+            };
+        }
+
+        fire!(Event::BeforeInstruction);
+        let instr_ptr = self.instr_ptr;
+        let error = |kind| Error {
+            ip: instr_ptr,
+            kind,
+        };
+        let Decoded {
+            instr,
+            reads,
+            read_count,
+            write,
+        } = self.decode()?;
+        for &(value, addr) in &reads[..read_count] {
+            if let Some(addr) = addr {
+                fire!(Event::Read { addr, value });
+            }
+        }
+        let base = self.relative_base;
+        let [(a, _), (b, _)] = reads;
+
+        let mut next = instr_ptr + OpCode::value_count(instr.op);
+        let mut relative_base = base;
+        // The value for the write parameter; instructions without one give 0.
+        let result = match instr.op {
+            OpCode::Halt => {
+                fire!(Event::Halt);
+                return Ok(Stop::Machine(State::Halted));
             }
-            OpCode::LessThan => {
-                let result_ptr = program[instr_ptr + 3] as usize;
-                if get_operand(instr_ptr + 1, instr.modes[0], program)
-                    < get_operand(instr_ptr + 2, instr.modes[1], program)
-                {
-                    program[result_ptr] = 1;
-                } else {
-                    program[result_ptr] = 0;
+            OpCode::Add => self.overflow.add(a, b).ok_or(error(ErrorKind::Overflow))?,
+            OpCode::Mul => self.overflow.mul(a, b).ok_or(error(ErrorKind::Overflow))?,
+            OpCode::LessThan => (a < b) as i32,
+            OpCode::Equals => (a == b) as i32,
+            OpCode::Input => {
+                fire!(Event::InputRequested);
+                match self.input.front() {
+                    Some(&value) => value,
+                    None => return Ok(Stop::Machine(State::AwaitingInput)),
                 }
-                instr_ptr += OpCode::value_count(instr.op);
             }
-            OpCode::Equals => {
-                let result_ptr = program[instr_ptr + 3] as usize;
-                if get_operand(instr_ptr + 1, instr.modes[0], program)
-                    == get_operand(instr_ptr + 2, instr.modes[1], program)
-                {
-                    program[result_ptr] = 1;
-                } else {
-                    program[result_ptr] = 0;
+            OpCode::Output => {
+                fire!(Event::Output(a));
+                0
+            }
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                if (a != 0) == (instr.op == OpCode::JumpIfTrue) {
+                    next = checked_address(&self.memory, b as i64).map_err(error)?;
                 }
-                instr_ptr += OpCode::value_count(instr.op);
+                0
             }
-            OpCode::Err => {
-                panic!("1202 program error");
+            OpCode::AdjustBase => {
+                relative_base = self
+                    .overflow
+                    .add(base, a)
+                    .ok_or(error(ErrorKind::Overflow))?;
+                0
             }
+        };
+        if let Some(addr) = write {
+            fire!(Event::Write {
+                addr,
+                value: result
+            });
         }
+
+        match instr.op {
+            OpCode::Input => {
+                self.input.pop_front();
+            }
+            OpCode::Output => self.output.push(a),
+            _ => {}
+        }
+        if let Some(addr) = write {
+            self.memory[addr] = result;
+        }
+        self.relative_base = relative_base;
+        self.instr_ptr = next;
+        self.steps += 1;
+        fire!(Event::AfterInstruction { ip: instr_ptr });
+        Ok(Stop::Machine(State::Running))
     }
+}
 
-    output
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Error {
+    pub ip: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnknownOpCode(i32),
+    UnknownMode(i32),
+    ImmediateWrite,
+    ExtraModes(i32),
+    Overflow,
+    /// An address outside the machine's memory, whether an operand, a jump
+    /// target or the ip itself.
+    OutOfBounds(i64),
+    /// An opcode beyond the machine's instruction set, and the set it needs.
+    OpCodeNotInSet(i32, InstructionSet),
+    /// A parameter mode beyond the machine's instruction set, and the set it needs.
+    ModeNotInSet(i32, InstructionSet),
 }
 
-fn get_operand(ptr: usize, mode: ParameterMode, program: &Vec<i32>) -> i32 {
-    match mode {
-        ParameterMode::Position => {
-            let operand_ptr = program[ptr] as usize;
-            program[operand_ptr]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::UnknownOpCode(code) => {
+                write!(f, "1202 program error: unknown opcode {}", code)?
+            }
+            ErrorKind::UnknownMode(mode) => write!(f, "unrecognized parameter mode {}", mode)?,
+            ErrorKind::ImmediateWrite => write!(f, "immediate mode on a write parameter")?,
+            ErrorKind::ExtraModes(word) => {
+                write!(f, "mode digits beyond instruction arity in {}", word)?
+            }
+            ErrorKind::Overflow => write!(f, "arithmetic overflow")?,
+            ErrorKind::OutOfBounds(addr) => write!(f, "address {} is outside memory", addr)?,
+            ErrorKind::OpCodeNotInSet(code, set) => {
+                write!(f, "opcode {} needs the {} instruction set", code, set)?
+            }
+            ErrorKind::ModeNotInSet(mode, set) => write!(
+                f,
+                "parameter mode {} needs the {} instruction set",
+                mode, set
+            )?,
         }
-        ParameterMode::Immediate => program[ptr],
+        write!(f, " at ip {}", self.ip)
     }
 }
 
+impl std::error::Error for Error {}
+
+/// The address a position or relative mode parameter at `ptr` refers to,
+/// which must be inside `program`.
+fn address(
+    ptr: usize,
+    mode: ParameterMode,
+    program: &Memory,
+    base: i32,
+) -> Result<usize, ErrorKind> {
+    let word = load(program, ptr)? as i64;
+    let addr = match mode {
+        ParameterMode::Relative => base as i64 + word,
+        _ => word,
+    };
+    checked_address(program, addr)
+}
+
+/// `addr` as an index into `program`, if it's inside it.
+fn checked_address(program: &Memory, addr: i64) -> Result<usize, ErrorKind> {
+    usize::try_from(addr)
+        .ok()
+        .filter(|&addr| addr < program.len())
+        .ok_or(ErrorKind::OutOfBounds(addr))
+}
+
+fn load(program: &Memory, addr: usize) -> Result<i32, ErrorKind> {
+    program
+        .get(addr)
+        .copied()
+        .ok_or(ErrorKind::OutOfBounds(addr as i64))
+}
+
+/// An instruction with its parameters resolved, as `Machine::decode` found it.
+#[derive(Debug, Clone)]
+struct Decoded {
+    instr: Instruction,
+    /// The read parameters' values, each with the address it came from unless
+    /// it was immediate. Only the first `read_count` are parameters.
+    reads: [(i32, Option<usize>); 2],
+    read_count: usize,
+    /// The address the write parameter refers to, if there is one.
+    write: Option<usize>,
+}
+
 #[derive(Debug, Clone)]
 struct Instruction {
     op: OpCode,
     modes: Vec<ParameterMode>,
 }
 
-impl std::convert::From<i32> for Instruction {
-    fn from(num: i32) -> Self {
-        let op = (num % 100).into();
-        let mut modes = Vec::new();
+impl Instruction {
+    fn decode(num: i32, strict: bool) -> Result<Self, ErrorKind> {
+        let op = OpCode::from_code(num % 100).ok_or(ErrorKind::UnknownOpCode(num % 100))?;
 
-        let modes_mask = num / 100;
-        match op {
-            OpCode::Add | OpCode::Mul | OpCode::LessThan | OpCode::Equals => {
-                let mode1 = modes_mask % 10;
-                let mode2 = (modes_mask / 10) % 10;
+        let mut modes_mask = num / 100;
+        let mut modes = Vec::new();
+        for &role in OpCode::info(op).params {
+            let mode = modes_mask % 10;
+            modes_mask /= 10;
 
-                modes.push(mode1.into());
-                modes.push(mode2.into());
-                modes.push(ParameterMode::Position); //technically not necessary but being explicit won't hurt for now
+            if !strict && role == Role::Write {
+                modes.push(match mode {
+                    2 => ParameterMode::Relative,
+                    _ => ParameterMode::Position,
+                });
+                continue;
             }
-            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
-                let mode1 = modes_mask % 10;
-                let mode2 = (modes_mask / 10) % 10;
 
-                modes.push(mode1.into());
-                modes.push(mode2.into());
+            let mode = ParameterMode::try_from(mode).map_err(ErrorKind::UnknownMode)?;
+            if let (ParameterMode::Immediate, Role::Write) = (mode, role) {
+                return Err(ErrorKind::ImmediateWrite);
             }
-            OpCode::Output => {
-                let mode1 = modes_mask % 10;
+            modes.push(mode);
+        }
 
-                modes.push(mode1.into());
-            }
-            _ => {}
+        if strict && modes_mask != 0 {
+            return Err(ErrorKind::ExtraModes(num));
         }
 
-        Instruction { op, modes }
+        Ok(Instruction { op, modes })
+    }
+
+    /// Fails if the instruction uses anything beyond `set`.
+    fn check(&self, set: InstructionSet) -> Result<(), ErrorKind> {
+        let needs = OpCode::instruction_set(self.op);
+        if needs > set {
+            return Err(ErrorKind::OpCodeNotInSet(OpCode::code(self.op), needs));
+        }
+        for &mode in &self.modes {
+            let needs = ParameterMode::instruction_set(mode);
+            if needs > set {
+                return Err(ErrorKind::ModeNotInSet(mode as i32, needs));
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum OpCode {
     Add,
     Mul,
@@ -144,59 +768,185 @@ enum OpCode {
     JumpIfFalse,
     LessThan,
     Equals,
+    AdjustBase,
     Halt,
-    Err,
 }
 
+/// How an instruction uses one of its parameters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Role {
+    Read,
+    Write,
+}
+
+/// Everything about an opcode except what it computes.
+#[derive(Debug)]
+struct OpInfo {
+    op: OpCode,
+    code: i32,
+    mnemonic: &'static str,
+    params: &'static [Role],
+    /// Whether it can move the ip somewhere other than the next instruction.
+    jumps: bool,
+    set: InstructionSet,
+}
+
+const READ_READ_WRITE: &[Role] = &[Role::Read, Role::Read, Role::Write];
+
+/// One entry per opcode, in the same order as `OpCode`.
+const OPCODES: [OpInfo; 10] = [
+    OpInfo {
+        op: OpCode::Add,
+        code: 1,
+        mnemonic: "add",
+        params: READ_READ_WRITE,
+        jumps: false,
+        set: InstructionSet::Basic,
+    },
+    OpInfo {
+        op: OpCode::Mul,
+        code: 2,
+        mnemonic: "mul",
+        params: READ_READ_WRITE,
+        jumps: false,
+        set: InstructionSet::Basic,
+    },
+    OpInfo {
+        op: OpCode::Input,
+        code: 3,
+        mnemonic: "in",
+        params: &[Role::Write],
+        jumps: false,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::Output,
+        code: 4,
+        mnemonic: "out",
+        params: &[Role::Read],
+        jumps: false,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::JumpIfTrue,
+        code: 5,
+        mnemonic: "jt",
+        params: &[Role::Read, Role::Read],
+        jumps: true,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::JumpIfFalse,
+        code: 6,
+        mnemonic: "jf",
+        params: &[Role::Read, Role::Read],
+        jumps: true,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::LessThan,
+        code: 7,
+        mnemonic: "lt",
+        params: READ_READ_WRITE,
+        jumps: false,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::Equals,
+        code: 8,
+        mnemonic: "eq",
+        params: READ_READ_WRITE,
+        jumps: false,
+        set: InstructionSet::Day5,
+    },
+    OpInfo {
+        op: OpCode::AdjustBase,
+        code: 9,
+        mnemonic: "arb",
+        params: &[Role::Read],
+        jumps: false,
+        set: InstructionSet::Full,
+    },
+    // Halt's value count is 1: no parameters, just the opcode itself.
+    OpInfo {
+        op: OpCode::Halt,
+        code: 99,
+        mnemonic: "hlt",
+        params: &[],
+        jumps: false,
+        set: InstructionSet::Basic,
+    },
+];
+
 #[derive(Debug, Copy, Clone)]
 enum ParameterMode {
-    Position,
-    Immediate,
+    Position = 0,
+    Immediate = 1,
+    Relative = 2,
 }
 
-impl std::convert::From<i32> for ParameterMode {
-    fn from(num: i32) -> Self {
-        match num {
-            0 => ParameterMode::Position,
-            1 => ParameterMode::Immediate,
-            _ => panic!("Unrecognized parameter mode"),
+impl ParameterMode {
+    fn instruction_set(mode: ParameterMode) -> InstructionSet {
+        match mode {
+            ParameterMode::Position => InstructionSet::Basic,
+            ParameterMode::Immediate => InstructionSet::Day5,
+            ParameterMode::Relative => InstructionSet::Full,
         }
     }
 }
 
-impl std::convert::From<i32> for OpCode {
-    fn from(num: i32) -> Self {
+impl std::convert::TryFrom<i32> for ParameterMode {
+    type Error = i32;
+
+    fn try_from(num: i32) -> Result<Self, i32> {
         match num {
-            1 => OpCode::Add,
-            2 => OpCode::Mul,
-            3 => OpCode::Input,
-            4 => OpCode::Output,
-            5 => OpCode::JumpIfTrue,
-            6 => OpCode::JumpIfFalse,
-            7 => OpCode::LessThan,
-            8 => OpCode::Equals,
-            99 => OpCode::Halt,
-            _ => OpCode::Err,
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            _ => Err(num),
         }
     }
 }
 
 impl OpCode {
+    fn from_code(code: i32) -> Option<OpCode> {
+        OPCODES
+            .iter()
+            .find(|info| info.code == code)
+            .map(|info| info.op)
+    }
+
+    fn info(instr: OpCode) -> &'static OpInfo {
+        &OPCODES[instr as usize]
+    }
+
+    /// The opcode word plus its parameters.
     fn value_count(instr: OpCode) -> usize {
-        match instr {
-            OpCode::Add => 4,
-            OpCode::Mul => 4,
-            OpCode::Input => 2,
-            OpCode::Output => 2,
-            OpCode::JumpIfTrue => 3,
-            OpCode::JumpIfFalse => 3,
-            OpCode::LessThan => 4,
-            OpCode::Equals => 4,
-            OpCode::Halt => 1, //maybe not technically correct, but seems to follow from definition of value count as opcode count + parameter count
-            _ => {
-                panic!("Undefined value count");
-            }
-        }
+        OpCode::info(instr).params.len() + 1
+    }
+
+    fn mnemonic(instr: OpCode) -> &'static str {
+        OpCode::info(instr).mnemonic
+    }
+
+    fn code(instr: OpCode) -> i32 {
+        OpCode::info(instr).code
+    }
+
+    fn instruction_set(instr: OpCode) -> InstructionSet {
+        OpCode::info(instr).set
+    }
+
+    fn jumps(instr: OpCode) -> bool {
+        OpCode::info(instr).jumps
+    }
+
+    /// Index of the parameter this instruction writes to, if any.
+    fn write_param(instr: OpCode) -> Option<usize> {
+        OpCode::info(instr)
+            .params
+            .iter()
+            .position(|&role| role == Role::Write)
     }
 }
 
@@ -204,6 +954,17 @@ impl OpCode {
 mod tests {
     use super::*;
 
+    /// Sums 1..=n for an input n, in a loop with a counter at [100] and the
+    /// sum at [101]. Shared by the submodules' tests.
+    pub(super) fn summer() -> Vec<i32> {
+        let mut program = vec![
+            3, 100, 1101, 0, 0, 101, 1006, 100, 20, 1, 100, 101, 101, 1001, 100, -1, 100, 1105, 1,
+            6, 4, 101, 99,
+        ];
+        program.resize(102, 0);
+        program
+    }
+
     #[test]
     fn test_add() {
         let mut test_prog = "1,0,0,0,99"
@@ -268,66 +1029,253 @@ mod tests {
         assert_eq!(test_prog[..], [1101, 100, -1, 4, 99]);
     }
 
+    fn run_with_input(machine: &mut Machine, value: i32) -> Vec<i32> {
+        machine.reset();
+        machine.push_input(value);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        machine.take_output()
+    }
+
     #[test]
     fn test_equals() {
-        let mut test_prog = "3,3,1108,-1,8,3,4,3,99"
-            .trim()
-            .split(",")
-            .map(|s| s.trim().parse::<i32>().unwrap())
-            .collect();
+        let program = Program::parse("3,3,1108,-1,8,3,4,3,99").unwrap();
+        let mut machine = Machine::new(&program);
 
-        let mut input = VecDeque::new();
-        input.push_front(8);
+        assert_eq!(run_with_input(&mut machine, 8), [1]);
+        assert_eq!(run_with_input(&mut machine, 9), [0]);
+    }
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [1]);
+    #[test]
+    fn test_less_than() {
+        let program = Program::parse("3,9,7,9,10,9,4,9,99,-1,8").unwrap();
+        let mut machine = Machine::new(&program);
 
-        let mut input = VecDeque::new();
-        input.push_front(9);
+        assert_eq!(run_with_input(&mut machine, 5), [1]);
+        assert_eq!(run_with_input(&mut machine, 8), [0]);
+    }
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [0]);
+    #[test]
+    fn test_jump() {
+        let program = Program::parse("3,3,1105,-1,9,1101,0,0,12,4,12,99,1").unwrap();
+        let mut machine = Machine::new(&program);
+
+        assert_eq!(run_with_input(&mut machine, 5), [1]);
+        assert_eq!(run_with_input(&mut machine, 0), [0]);
     }
 
     #[test]
-    fn test_less_than() {
-        let mut test_prog = "3,9,7,9,10,9,4,9,99,-1,8"
-            .trim()
-            .split(",")
-            .map(|s| s.trim().parse::<i32>().unwrap())
-            .collect();
+    fn test_out_of_bounds() {
+        let error = |program: Vec<i32>| Machine::new(program).run().unwrap_err();
+        assert_eq!(
+            error(vec![1, 100, 0, 0, 99]).kind,
+            ErrorKind::OutOfBounds(100)
+        );
+        assert_eq!(
+            error(vec![1101, 1, 1, -1, 99]).kind,
+            ErrorKind::OutOfBounds(-1)
+        );
+        assert_eq!(
+            error(vec![109, -1, 204, 0, 99]).kind,
+            ErrorKind::OutOfBounds(-1)
+        );
+        assert_eq!(error(vec![1105, 1, 9, 99]).kind, ErrorKind::OutOfBounds(9));
+        // Running off the end, a truncated instruction and no program at all.
+        assert_eq!(
+            error(vec![1101, 1, 1, 0]),
+            Error {
+                ip: 4,
+                kind: ErrorKind::OutOfBounds(4)
+            }
+        );
+        assert_eq!(error(vec![1101, 1]).kind, ErrorKind::OutOfBounds(2));
+        assert_eq!(error(vec![]).kind, ErrorKind::OutOfBounds(0));
+    }
 
-        let mut input = VecDeque::new();
-        input.push_front(5);
+    #[test]
+    fn test_reset() {
+        let program = Program::from(vec![1, 0, 0, 0, 99]);
+        let mut machine = Machine::new(&program).overflow(Overflow::Wrapping);
+        machine.run().unwrap();
+        assert_eq!(machine.memory()[0], 2);
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [1]);
+        machine.reset_with(&[(1, 4), (2, 4)]).unwrap();
+        assert_eq!(machine.steps(), 0);
+        machine.run().unwrap();
+        assert_eq!(machine.memory()[0], 198);
+        assert_eq!(program.to_vec(), [1, 0, 0, 0, 99]);
+        assert_eq!(machine.memory().shared_pages(&program.memory), 0);
 
-        let mut input = VecDeque::new();
-        input.push_front(8);
+        machine.reset();
+        assert_eq!(machine.memory().shared_pages(&program.memory), 1);
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [0]);
+        assert_eq!(
+            machine.reset_with(&[(1, 4), (5, 4)]),
+            Err("patch address 5 is outside the program (5 words)".to_string())
+        );
+        assert_eq!(machine.memory().to_vec(), [1, 0, 0, 0, 99]);
+
+        let mut words = vec![1, 0, 0, 0, 99];
+        assert!(apply_patches(&mut words, &[(0, 2), (9, 0)]).is_err());
+        assert_eq!(words, [1, 0, 0, 0, 99]);
+        assert_eq!(apply_patches(&mut words, &[(0, 2)]), Ok(()));
+        assert_eq!(words, [2, 0, 0, 0, 99]);
     }
 
     #[test]
-    fn test_jump() {
-        let mut test_prog = "3,3,1105,-1,9,1101,0,0,12,4,12,99,1"
-            .trim()
-            .split(",")
-            .map(|s| s.trim().parse::<i32>().unwrap())
-            .collect();
+    fn test_strict_mode() {
+        let mut machine = Machine::new(vec![11101, 1, 1, 0, 99]);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[0], 2);
 
-        let mut input = VecDeque::new();
-        input.push_front(5);
+        let mut machine = Machine::new(vec![11101, 1, 1, 0, 99]).strict(true);
+        assert_eq!(
+            machine.run(),
+            Err(Error {
+                ip: 0,
+                kind: ErrorKind::ImmediateWrite
+            })
+        );
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [1]);
+        let mut machine = Machine::new(vec![104, 7, 10004, 0, 99]).strict(true);
+        assert_eq!(
+            machine.run(),
+            Err(Error {
+                ip: 2,
+                kind: ErrorKind::ExtraModes(10004)
+            })
+        );
+        assert_eq!(machine.output(), [7]);
+    }
 
-        let mut input = VecDeque::new();
-        input.push_front(0);
+    #[test]
+    fn test_validate() {
+        let errors = validate(&[103, 0, 1102, 2, 3, 0, 204, 0, 98], InstructionSet::Day5);
+        assert_eq!(
+            errors,
+            [
+                Error {
+                    ip: 0,
+                    kind: ErrorKind::ImmediateWrite
+                },
+                Error {
+                    ip: 6,
+                    kind: ErrorKind::ModeNotInSet(2, InstructionSet::Full)
+                },
+                Error {
+                    ip: 8,
+                    kind: ErrorKind::UnknownOpCode(98)
+                },
+            ]
+        );
+
+        assert!(validate(&[1002, 4, 3, 4, 33], InstructionSet::Full).len() == 1);
+        assert!(validate(&[1002, 4, 3, 4, 99], InstructionSet::Full).is_empty());
+        assert_eq!(
+            validate(&[304, 0, 99], InstructionSet::Full)[0].kind,
+            ErrorKind::UnknownMode(3)
+        );
+    }
+
+    #[test]
+    fn test_instruction_sets() {
+        let mut machine = Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50])
+            .instruction_set(InstructionSet::Basic);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[0], 3500);
+
+        let mut machine =
+            Machine::new(vec![1101, 1, 1, 0, 99]).instruction_set(InstructionSet::Basic);
+        let error = machine.run().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ModeNotInSet(1, InstructionSet::Day5));
+        assert_eq!(
+            error.to_string(),
+            "parameter mode 1 needs the day5 instruction set at ip 0"
+        );
+
+        let mut machine = Machine::new(vec![3, 0, 99]).instruction_set(InstructionSet::Basic);
+        assert_eq!(
+            machine.run().unwrap_err().kind,
+            ErrorKind::OpCodeNotInSet(3, InstructionSet::Day5)
+        );
+
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut program = quine.clone();
+        program.resize(102, 0);
+
+        let mut machine = Machine::new(program.clone()).instruction_set(InstructionSet::Day5);
+        assert_eq!(
+            machine.run().unwrap_err().kind,
+            ErrorKind::OpCodeNotInSet(9, InstructionSet::Full)
+        );
+
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.output(), &quine[..]);
+        assert_eq!(machine.relative_base(), 16);
+    }
+
+    #[test]
+    fn test_relative_write() {
+        let mut machine = Machine::new(vec![109, 8, 21101, 2, 3, -1, 99, 0]);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[7], 5);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = vec![1102, 65536, 65536, 5, 99, 0];
+
+        let mut machine = Machine::new(program.clone());
+        assert_eq!(
+            machine.run(),
+            Err(Error {
+                ip: 0,
+                kind: ErrorKind::Overflow
+            })
+        );
+
+        let mut machine = Machine::new(program.clone()).overflow(Overflow::Wrapping);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[5], 0);
+
+        let mut machine = Machine::new(program).overflow(Overflow::Saturating);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[5], i32::MAX);
+
+        let mut machine =
+            Machine::new(vec![1101, i32::MIN, -1, 5, 99, 0]).overflow(Overflow::Saturating);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[5], i32::MIN);
+    }
+
+    #[test]
+    fn test_opcode_table() {
+        for (i, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.op as usize, i);
+            assert_eq!(OpCode::from_code(info.code), Some(info.op));
+            assert!(info.params.iter().filter(|&&r| r == Role::Write).count() <= 1);
+            assert!(info.params.iter().filter(|&&r| r == Role::Read).count() <= 2);
+        }
+        assert_eq!(OpCode::from_code(0), None);
+        assert_eq!(OpCode::write_param(OpCode::Input), Some(0));
+        assert!(OpCode::jumps(OpCode::JumpIfFalse));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = parse_program("1002,4,3,4,33,104,-1,99").unwrap();
+
+        assert_eq!(disassemble(&program, 0), ("mul [4], 3, [4]".to_string(), 4));
+        assert_eq!(disassemble(&program, 4), ("data 33".to_string(), 1));
+        assert_eq!(disassemble(&program, 5), ("out -1".to_string(), 2));
+        assert_eq!(disassemble(&program, 7), ("hlt".to_string(), 1));
+        assert_eq!(disassemble(&program, 8), ("<out of bounds>".to_string(), 0));
 
-        let output = run_program(&mut test_prog, input);
-        assert_eq!(output[..], [0]);
+        let program = parse_program("109,8,21101,2,3,-1,204,3").unwrap();
+        assert_eq!(disassemble(&program, 0), ("arb 8".to_string(), 2));
+        assert_eq!(disassemble(&program, 2).0, "add 2, 3, [rb-1]");
+        assert_eq!(disassemble(&program, 6).0, "out [rb+3]");
     }
 }
@@ -0,0 +1,102 @@
+//! Interpreter throughput over a corpus of programs with known outputs, kept
+//! in `benches/corpus` in the conformance case format.
+
+use super::conformance::Case;
+use super::{Machine, State};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many instructions a case ran in how long.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub name: String,
+    pub runs: usize,
+    /// Instructions executed over all runs.
+    pub steps: usize,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    pub fn steps_per_sec(&self) -> f64 {
+        self.steps as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>7} runs {:>12} steps {:>9.2?} {:>8.1} M/s",
+            self.name,
+            self.runs,
+            self.steps,
+            self.elapsed,
+            self.steps_per_sec() / 1e6
+        )
+    }
+}
+
+/// Checks the case, then runs it repeatedly on one reset machine for at least
+/// `min_time`.
+pub fn measure(case: &Case, min_time: Duration) -> Result<Measurement, String> {
+    case.run().map_err(|e| format!("{}: {}", case.name, e))?;
+
+    let mut machine = Machine::new(case.initial_memory());
+    let mut runs = 0;
+    let mut steps = 0;
+    let start = Instant::now();
+
+    loop {
+        machine.reset();
+        for &value in &case.input {
+            machine.push_input(value);
+        }
+        match machine.run() {
+            Ok(State::Halted) => {}
+            Ok(_) => return Err(format!("{}: ran out of input", case.name)),
+            Err(e) => return Err(format!("{}: {}", case.name, e)),
+        }
+        runs += 1;
+        steps += machine.steps();
+
+        let elapsed = start.elapsed();
+        if elapsed >= min_time {
+            return Ok(Measurement {
+                name: case.name.clone(),
+                runs,
+                steps,
+                elapsed,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::conformance::{load_cases, parse_cases};
+
+    use std::path::Path;
+
+    #[test]
+    fn test_corpus() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/corpus/programs.txt");
+        let cases = load_cases(path).unwrap();
+        assert_eq!(cases.len(), 5);
+        for case in &cases {
+            assert_eq!(case.run(), Ok(()), "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn test_measure() {
+        let cases = parse_cases("program: 1101,0,0,0,99\nmemory: 0,0,0,0,99").unwrap();
+        let measurement = measure(&cases[0], Duration::from_millis(1)).unwrap();
+        assert!(measurement.runs >= 1);
+        assert_eq!(measurement.steps, measurement.runs);
+
+        let cases = parse_cases("program: 104,1,99\noutput: 2").unwrap();
+        assert!(measure(&cases[0], Duration::from_millis(1)).is_err());
+    }
+}
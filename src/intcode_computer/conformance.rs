@@ -0,0 +1,232 @@
+use super::{parse_program, Machine, State};
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A program run with some input, and what it should produce.
+///
+/// Case files hold blank-line separated cases like:
+///
+/// ```text
+/// # equal to 8, position mode
+/// program: 3,9,8,9,10,9,4,9,99,-1,8
+/// input: 8
+/// output: 1
+/// ```
+///
+/// `input` is optional, and at least one of `output` and `memory` (the final
+/// memory) must be given. `size` pads the program with zeros to that many
+/// words. Blocks without a program are treated as comments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    pub program: Vec<i32>,
+    pub size: Option<usize>,
+    pub input: Vec<i32>,
+    pub output: Option<Vec<i32>>,
+    pub memory: Option<Vec<i32>>,
+}
+
+impl Case {
+    /// The program padded to `size`, as the machine starts with it. Not to be
+    /// confused with `memory`, what it should end with.
+    pub fn initial_memory(&self) -> Vec<i32> {
+        let mut memory = self.program.clone();
+        if let Some(size) = self.size {
+            if size > memory.len() {
+                memory.resize(size, 0);
+            }
+        }
+        memory
+    }
+
+    /// Runs the case to completion, describing the first mismatch on failure.
+    pub fn run(&self) -> Result<(), String> {
+        let mut machine = Machine::new(self.initial_memory());
+        for &value in &self.input {
+            machine.push_input(value);
+        }
+
+        match machine.run() {
+            Ok(State::Halted) => {}
+            Ok(_) => return Err("ran out of input".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        if let Some(output) = &self.output {
+            if machine.output() != &output[..] {
+                return Err(format!(
+                    "expected output {:?}, got {:?}",
+                    output,
+                    machine.output()
+                ));
+            }
+        }
+        if let Some(memory) = &self.memory {
+            if *machine.memory() != memory[..] {
+                return Err(format!(
+                    "expected memory {:?}, got {:?}",
+                    memory,
+                    machine.memory()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_cases(text: &str) -> Result<Vec<Case>, String> {
+    let mut cases = Vec::new();
+
+    // Cases end at lines that are blank once trimmed, which also covers CRLF.
+    let mut blocks = vec![Vec::new()];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blocks.push(Vec::new());
+        } else {
+            blocks.last_mut().unwrap().push(line);
+        }
+    }
+
+    for block in blocks {
+        let mut keys = Vec::new();
+        let mut name = None;
+        let mut program = None;
+        let mut size = None;
+        let mut input = Vec::new();
+        let mut output = None;
+        let mut memory = None;
+
+        for line in block {
+            if let Some(comment) = line.strip_prefix('#') {
+                name.get_or_insert_with(|| comment.trim().to_string());
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap().trim();
+            if keys.contains(&key) {
+                return Err(format!("{} is given twice in one case: {}", key, line));
+            }
+            keys.push(key);
+            let words = parts
+                .next()
+                .ok_or(format!("expected key: value, got {}", line))
+                .and_then(|v| parse_program(v).map_err(|e| format!("{}: {}", line, e)))?;
+
+            match key {
+                "program" => program = Some(words),
+                "size" => match words[..] {
+                    [words] if words >= 0 => size = Some(words as usize),
+                    _ => return Err(format!("bad size {}", line)),
+                },
+                "input" => input = words,
+                "output" => output = Some(words),
+                "memory" => memory = Some(words),
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+
+        let program = match program {
+            Some(program) => program,
+            None => continue,
+        };
+        let name = name.unwrap_or_else(|| format!("case {}", cases.len() + 1));
+        if output.is_none() && memory.is_none() {
+            return Err(format!("{} has no expected output or memory", name));
+        }
+
+        cases.push(Case {
+            name,
+            program,
+            size,
+            input,
+            output,
+            memory,
+        });
+    }
+
+    Ok(cases)
+}
+
+pub fn load_cases<P: AsRef<Path>>(path: P) -> io::Result<Vec<Case>> {
+    parse_cases(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cases() {
+        let cases = parse_cases("# header\n\n# echo\nprogram: 3,0,4,0,99\ninput: 7\noutput: 7\n\nprogram: 99\nmemory: 99\n").unwrap();
+
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "echo");
+        assert_eq!(cases[0].input, [7]);
+        assert_eq!(cases[0].output, Some(vec![7]));
+        assert_eq!(cases[0].memory, None);
+        assert_eq!(cases[1].name, "case 2");
+
+        let padded = parse_cases("program: 99\nsize: 3\nmemory: 99,0,0").unwrap();
+        assert_eq!(padded[0].initial_memory(), [99, 0, 0]);
+        assert_eq!(padded[0].run(), Ok(()));
+        assert!(parse_cases("program: 99\nsize: 1,2\noutput: 1").is_err());
+
+        assert!(parse_cases("program: 99").is_err());
+        assert!(parse_cases("program: 99\nouput: 1").is_err());
+    }
+
+    #[test]
+    fn test_separators() {
+        let text = "# a\nprogram: 104,1,99\noutput: 1\n \t\n# b\nprogram: 104,2,99\noutput: 3\n";
+        for text in [text.to_string(), text.replace('\n', "\r\n")] {
+            let cases = parse_cases(&text).unwrap();
+            let names: Vec<&str> = cases.iter().map(|case| case.name.as_str()).collect();
+            assert_eq!(names, ["a", "b"]);
+            assert!(cases[1].run().is_err());
+        }
+
+        let merged = parse_cases("program: 104,1,99\noutput: 1\nprogram: 99\noutput: 2");
+        assert_eq!(
+            merged,
+            Err("program is given twice in one case: program: 99".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failing_case() {
+        let cases = parse_cases("program: 104,1,99\noutput: 2").unwrap();
+        assert_eq!(
+            cases[0].run(),
+            Err("expected output [2], got [1]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_conformance_suite() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for path in paths {
+            for case in load_cases(&path).unwrap() {
+                count += 1;
+                if let Err(e) = case.run() {
+                    failures.push(format!("{} / {}: {}", path.display(), case.name, e));
+                }
+            }
+        }
+
+        assert!(count > 0);
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
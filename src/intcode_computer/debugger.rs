@@ -0,0 +1,233 @@
+use super::expr::Expr;
+use super::{Error, Machine, State};
+
+/// Stops before the instruction at `addr` runs, or before every instruction if
+/// there's no address, as long as the condition (if any) holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub addr: Option<usize>,
+    pub condition: Option<Expr>,
+}
+
+/// Stops after an instruction changes the value at `addr`, as long as the
+/// condition (if any) holds afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub addr: usize,
+    pub condition: Option<Expr>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stop {
+    /// Breakpoint `n` was hit; the machine is paused before the instruction runs.
+    Breakpoint(usize),
+    /// Watchpoint `n` was hit; the value changed from `old` to `new`.
+    Watchpoint { index: usize, old: i32, new: i32 },
+    /// The machine halted or needs input.
+    Machine(State),
+    /// `cont_for` ran out of steps.
+    StepLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    pub machine: Machine,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+fn parse_condition(condition: Option<&str>) -> Result<Option<Expr>, String> {
+    condition.map(Expr::parse).transpose()
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Debugger {
+            machine,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Returns the new breakpoint's index.
+    pub fn add_breakpoint(
+        &mut self,
+        addr: Option<usize>,
+        condition: Option<&str>,
+    ) -> Result<usize, String> {
+        let condition = parse_condition(condition)?;
+        self.breakpoints.push(Breakpoint { addr, condition });
+        Ok(self.breakpoints.len() - 1)
+    }
+
+    /// Returns the new watchpoint's index.
+    pub fn add_watchpoint(
+        &mut self,
+        addr: usize,
+        condition: Option<&str>,
+    ) -> Result<usize, String> {
+        let condition = parse_condition(condition)?;
+        self.watchpoints.push(Watchpoint { addr, condition });
+        Ok(self.watchpoints.len() - 1)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    fn hit_breakpoint(&self) -> Option<usize> {
+        let machine = &self.machine;
+        self.breakpoints.iter().position(|b| {
+            b.addr.is_none_or(|addr| addr == machine.instr_ptr)
+                && b.condition.as_ref().is_none_or(|c| c.is_true(machine))
+        })
+    }
+
+    /// Runs one instruction, reporting a watchpoint it triggered or the
+    /// machine stopping. Breakpoints are ignored.
+    pub fn step(&mut self) -> Result<Option<Stop>, Error> {
+        let before: Vec<i32> = self
+            .watchpoints
+            .iter()
+            .map(|w| self.machine.memory.get(w.addr).copied().unwrap_or(0))
+            .collect();
+
+        let state = self.machine.step()?;
+        if state != State::Running {
+            return Ok(Some(Stop::Machine(state)));
+        }
+
+        for (index, (w, &old)) in self.watchpoints.iter().zip(&before).enumerate() {
+            let new = self.machine.memory.get(w.addr).copied().unwrap_or(0);
+            if new != old
+                && w.condition
+                    .as_ref()
+                    .is_none_or(|c| c.is_true(&self.machine))
+            {
+                return Ok(Some(Stop::Watchpoint { index, old, new }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit or the machine stops. At
+    /// least one instruction runs, so continuing from a breakpoint moves past it.
+    pub fn cont(&mut self) -> Result<Stop, Error> {
+        self.cont_for(usize::MAX)
+    }
+
+    /// Same as `cont`, but gives up with `Stop::StepLimit` after `steps`
+    /// instructions, unless the last of them lands on a breakpoint.
+    pub fn cont_for(&mut self, steps: usize) -> Result<Stop, Error> {
+        if steps == 0 {
+            return Ok(Stop::StepLimit);
+        }
+        if let Some(stop) = self.step()? {
+            return Ok(stop);
+        }
+
+        for _ in 1..steps {
+            if let Some(index) = self.hit_breakpoint() {
+                return Ok(Stop::Breakpoint(index));
+            }
+            if let Some(stop) = self.step()? {
+                return Ok(stop);
+            }
+        }
+        if let Some(index) = self.hit_breakpoint() {
+            return Ok(Stop::Breakpoint(index));
+        }
+        Ok(Stop::StepLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::tests::summer;
+
+    fn debugger(n: i32) -> Debugger {
+        let mut machine = Machine::new(summer());
+        machine.push_input(n);
+        Debugger::new(machine)
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut debugger = debugger(10);
+        debugger
+            .add_breakpoint(Some(9), Some("mem[100] < 8"))
+            .unwrap();
+
+        assert_eq!(debugger.cont(), Ok(Stop::Breakpoint(0)));
+        assert_eq!(debugger.machine.instr_ptr(), 9);
+        assert_eq!(debugger.machine.memory()[100], 7);
+        assert_eq!(debugger.machine.memory()[101], 10 + 9 + 8);
+
+        assert_eq!(debugger.cont(), Ok(Stop::Breakpoint(0)));
+        assert_eq!(debugger.machine.memory()[100], 6);
+    }
+
+    #[test]
+    fn test_addressless_breakpoint() {
+        let mut debugger = debugger(3);
+        debugger
+            .add_breakpoint(None, Some("out_count >= 1"))
+            .unwrap();
+
+        assert_eq!(debugger.cont(), Ok(Stop::Breakpoint(0)));
+        assert_eq!(debugger.machine.output(), [6]);
+        assert_eq!(debugger.cont(), Ok(Stop::Machine(State::Halted)));
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut debugger = debugger(4);
+        debugger.add_watchpoint(101, Some("mem[101] > 5")).unwrap();
+
+        assert_eq!(
+            debugger.cont(),
+            Ok(Stop::Watchpoint {
+                index: 0,
+                old: 4,
+                new: 7
+            })
+        );
+        assert_eq!(debugger.machine.instr_ptr(), 13);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut debugger = debugger(10);
+        assert_eq!(debugger.cont_for(5), Ok(Stop::StepLimit));
+        assert_eq!(debugger.machine.steps(), 5);
+        assert_eq!(debugger.cont_for(1000), Ok(Stop::Machine(State::Halted)));
+    }
+
+    #[test]
+    fn test_breakpoint_at_step_limit() {
+        // The breakpoint at 9 is reached by exactly the third instruction.
+        let mut debugger = debugger(10);
+        debugger.add_breakpoint(Some(9), None).unwrap();
+        assert_eq!(debugger.cont_for(2), Ok(Stop::StepLimit));
+        assert_eq!(debugger.cont_for(1), Ok(Stop::Breakpoint(0)));
+        assert_eq!(debugger.machine.instr_ptr(), 9);
+    }
+
+    #[test]
+    fn test_bad_condition() {
+        let mut debugger = debugger(1);
+        assert!(debugger.add_breakpoint(Some(0), Some("mem[")).is_err());
+        assert!(debugger.breakpoints().is_empty());
+    }
+}
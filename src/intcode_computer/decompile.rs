@@ -0,0 +1,632 @@
+//! Turns a program back into structured pseudo-code, recovering `if`/`else`
+//! and loops from the control flow graph.
+//!
+//! Memory cells are named `v` followed by their address, and relative mode
+//! cells `rb[offset]`. A comparison whose result is only used by the jump
+//! straight after it is folded into the jump's condition. Code the graph
+//! can't see into, such as self-modifying code and jumps to computed
+//! addresses, ends its branch with a `// not decompiled` comment. Flow that
+//! doesn't nest shows up as `goto`s to labels named after block addresses.
+
+use super::flow::{decode_at, Graph, Terminator};
+use super::{Instruction, OpCode, ParameterMode};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Const(i32),
+    Var(usize),
+    /// A relative mode cell.
+    Local(i32),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+}
+
+impl Expr {
+    fn binary(left: Expr, op: &'static str, right: Expr) -> Expr {
+        Expr::Binary(Box::new(left), op, Box::new(right))
+    }
+
+    /// The condition for `self` being non-zero, or zero if `nonzero` is false.
+    fn truth(self, nonzero: bool) -> Expr {
+        let condition = match self {
+            Expr::Binary(_, "<", _) | Expr::Binary(_, "==", _) => self,
+            _ => return Expr::binary(self, if nonzero { "!=" } else { "==" }, Expr::Const(0)),
+        };
+        if nonzero {
+            condition
+        } else {
+            condition.negate()
+        }
+    }
+
+    fn negate(self) -> Expr {
+        match self {
+            Expr::Binary(left, op, right) => {
+                let op = match op {
+                    "<" => ">=",
+                    ">=" => "<",
+                    "==" => "!=",
+                    "!=" => "==",
+                    _ => return Expr::binary(Expr::Binary(left, op, right), "==", Expr::Const(0)),
+                };
+                Expr::Binary(left, op, right)
+            }
+            _ => Expr::binary(self, "==", Expr::Const(0)),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Var(addr) => write!(f, "v{}", addr),
+            Expr::Local(offset) => write!(f, "rb[{}]", offset),
+            Expr::Binary(left, op, right) => write!(f, "{} {} {}", left, op, right),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Line(String),
+    /// The start of a block, shown only if something jumps to it.
+    Label(usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    Loop(Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    DoWhile(Vec<Stmt>, Expr),
+    Break,
+    Continue,
+    Goto(usize),
+    Halt,
+    NotDecompiled(String),
+}
+
+/// Whether control never runs off the end of `stmts`.
+fn jumps_away(stmts: &[Stmt]) -> bool {
+    matches!(
+        stmts.last(),
+        Some(Stmt::Break)
+            | Some(Stmt::Continue)
+            | Some(Stmt::Goto(_))
+            | Some(Stmt::Halt)
+            | Some(Stmt::NotDecompiled(_))
+    )
+}
+
+/// Whether `stmts` continue the loop they're in, not counting nested loops.
+fn continues(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Continue => true,
+        Stmt::If(_, then, otherwise) => continues(then) || continues(otherwise),
+        _ => false,
+    })
+}
+
+fn operand(program: &[i32], ip: usize, instr: &Instruction, i: usize) -> Expr {
+    let word = program[ip + 1 + i];
+    match instr.modes[i] {
+        ParameterMode::Position => Expr::Var(word as usize),
+        ParameterMode::Immediate => Expr::Const(word),
+        ParameterMode::Relative => Expr::Local(word),
+    }
+}
+
+/// Cells an instruction reads and writes in position mode.
+fn accesses(program: &[i32], ip: usize, instr: &Instruction) -> (Vec<usize>, Option<usize>) {
+    let mut reads = Vec::new();
+    let mut write = None;
+    for (i, &mode) in instr.modes.iter().enumerate() {
+        if let ParameterMode::Position = mode {
+            let addr = program[ip + 1 + i] as usize;
+            if OpCode::write_param(instr.op) == Some(i) {
+                write = Some(addr);
+            } else {
+                reads.push(addr);
+            }
+        }
+    }
+    (reads, write)
+}
+
+/// The value an arithmetic or comparison instruction computes.
+fn value(program: &[i32], ip: usize, instr: &Instruction) -> Expr {
+    let a = operand(program, ip, instr, 0);
+    let b = operand(program, ip, instr, 1);
+    match (instr.op, a, b) {
+        (OpCode::Add, a, Expr::Const(0)) | (OpCode::Add, Expr::Const(0), a) => a,
+        (OpCode::Add, a, Expr::Const(b)) if b < 0 => Expr::binary(a, "-", Expr::Const(-b)),
+        (OpCode::Add, a, b) => Expr::binary(a, "+", b),
+        (OpCode::Mul, a, Expr::Const(1)) | (OpCode::Mul, Expr::Const(1), a) => a,
+        (OpCode::Mul, a, b) => Expr::binary(a, "*", b),
+        (OpCode::LessThan, a, b) => Expr::binary(a, "<", b),
+        (_, a, b) => Expr::binary(a, "==", b),
+    }
+}
+
+fn statement(program: &[i32], ip: usize, instr: &Instruction) -> String {
+    let dst = |i| operand(program, ip, instr, i);
+    match instr.op {
+        OpCode::Input => format!("{} = input()", dst(0)),
+        OpCode::Output => format!("output({})", dst(0)),
+        OpCode::AdjustBase => match dst(0) {
+            Expr::Const(offset) if offset < 0 => format!("rb -= {}", -offset),
+            offset => format!("rb += {}", offset),
+        },
+        _ => format!("{} = {}", dst(2), value(program, ip, instr)),
+    }
+}
+
+struct Decompiler<'a> {
+    program: &'a [i32],
+    graph: &'a Graph,
+    /// Loop headers and the blocks in their loops.
+    loops: BTreeMap<usize, BTreeSet<usize>>,
+    post_dominators: BTreeMap<usize, BTreeSet<usize>>,
+    emitted: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+}
+
+/// A loop being emitted: its header and the block it exits to, if any.
+type Context = (usize, Option<usize>);
+
+impl<'a> Decompiler<'a> {
+    fn new(program: &'a [i32], graph: &'a Graph, entry: usize) -> Self {
+        let dominators = graph.dominators(entry);
+        let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &block in dominators.keys() {
+            for next in graph.successors(block) {
+                predecessors.entry(next).or_default().push(block);
+            }
+        }
+
+        // A loop is everything that reaches a back edge to its header
+        // without going through the header.
+        let mut loops: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (&block, dominated_by) in &dominators {
+            for header in graph.successors(block) {
+                if !dominated_by.contains(&header) {
+                    continue;
+                }
+                let body = loops
+                    .entry(header)
+                    .or_insert_with(|| Some(header).into_iter().collect());
+                let mut pending = vec![block];
+                while let Some(block) = pending.pop() {
+                    if body.insert(block) {
+                        pending.extend(predecessors.get(&block).into_iter().flatten());
+                    }
+                }
+            }
+        }
+
+        Decompiler {
+            program,
+            graph,
+            loops,
+            post_dominators: graph.post_dominators(entry),
+            emitted: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+        }
+    }
+
+    fn instruction(&self, ip: usize) -> Instruction {
+        decode_at(self.program, ip).expect("blocks only hold decodable instructions")
+    }
+
+    /// Where control goes after leaving the loop at `header`: preferably
+    /// where the header's own test leads, then a latch's.
+    fn exit(&self, header: usize) -> Option<usize> {
+        let body = &self.loops[&header];
+        let outside = |block: &usize| -> Vec<usize> {
+            self.graph
+                .successors(*block)
+                .into_iter()
+                .filter(|next| !body.contains(next))
+                .collect()
+        };
+        let latches = body
+            .iter()
+            .filter(|&&block| self.graph.successors(block).contains(&header));
+
+        Some(header)
+            .iter()
+            .chain(latches)
+            .chain(body.iter())
+            .flat_map(outside)
+            .next()
+    }
+
+    /// Whether the value at `addr` might be read after `block` before
+    /// being overwritten.
+    fn live_after(&self, block: usize, addr: usize) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut pending = self.graph.successors(block);
+
+        'blocks: while let Some(block) = pending.pop() {
+            if !seen.insert(block) {
+                continue;
+            }
+            let block = &self.graph.blocks[&block];
+            for &ip in &block.instructions {
+                let (reads, write) = accesses(self.program, ip, &self.instruction(ip));
+                if reads.contains(&addr) {
+                    return true;
+                }
+                if write == Some(addr) {
+                    continue 'blocks;
+                }
+            }
+            match block.end {
+                Terminator::Indirect { .. } | Terminator::Unknown(_) => return true,
+                end => pending.extend(end.successors()),
+            }
+        }
+        false
+    }
+
+    fn sequence(
+        &mut self,
+        mut block: usize,
+        follow: Option<usize>,
+        loops: &mut Vec<Context>,
+        out: &mut Vec<Stmt>,
+    ) {
+        loop {
+            if Some(block) == follow {
+                return;
+            }
+            if let Some(&(header, exit)) = loops.last() {
+                if block == header {
+                    out.push(Stmt::Continue);
+                    return;
+                }
+                if Some(block) == exit {
+                    out.push(Stmt::Break);
+                    return;
+                }
+            }
+            // Blocks that are only a marker are repeated rather than shared.
+            if self.emitted.contains(&block) && !self.graph.blocks[&block].instructions.is_empty() {
+                self.gotos.insert(block);
+                out.push(Stmt::Goto(block));
+                return;
+            }
+
+            let next = if self.loops.contains_key(&block) {
+                let exit = self.exit(block);
+                let mut body = Vec::new();
+                loops.push((block, exit));
+                if let Some(next) = self.block(block, loops, &mut body) {
+                    self.sequence(next, None, loops, &mut body);
+                }
+                loops.pop();
+                out.push(Stmt::Loop(body));
+                exit
+            } else {
+                self.block(block, loops, out)
+            };
+
+            match next {
+                Some(next) => block = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Emits one block, returning the block that follows it if control can
+    /// reach one without a jump out of the current construct.
+    fn block(
+        &mut self,
+        start: usize,
+        loops: &mut Vec<Context>,
+        out: &mut Vec<Stmt>,
+    ) -> Option<usize> {
+        self.emitted.insert(start);
+        out.push(Stmt::Label(start));
+
+        let graph = self.graph;
+        let block = &graph.blocks[&start];
+        let mut body: Vec<usize> = block.instructions.clone();
+        if !matches!(block.end, Terminator::Fall(_)) {
+            body.pop();
+        }
+
+        match block.end {
+            Terminator::Fall(next) | Terminator::Goto(next) => {
+                self.statements(&body, out);
+                Some(next)
+            }
+            Terminator::Halt => {
+                self.statements(&body, out);
+                out.push(Stmt::Halt);
+                None
+            }
+            Terminator::Indirect { ip, fall } => {
+                self.statements(&body, out);
+                let instr = self.instruction(ip);
+                let jump = Stmt::NotDecompiled(format!(
+                    "jump at {} to {}",
+                    ip,
+                    operand(self.program, ip, &instr, 1)
+                ));
+                if fall.is_some() {
+                    let condition = operand(self.program, ip, &instr, 0)
+                        .truth(matches!(instr.op, OpCode::JumpIfTrue));
+                    out.push(Stmt::If(condition, vec![jump], Vec::new()));
+                } else {
+                    out.push(jump);
+                }
+                fall
+            }
+            Terminator::Unknown(addr) => {
+                self.statements(&body, out);
+                out.push(Stmt::NotDecompiled(match decode_at(self.program, addr) {
+                    Some(_) => format!("code at {} is modified at run time", addr),
+                    None => format!("{} isn't code", addr),
+                }));
+                None
+            }
+            Terminator::Branch { ip, taken, fall } => {
+                let instr = self.instruction(ip);
+                let mut tested = operand(self.program, ip, &instr, 0);
+
+                // Fold in a comparison made just for this jump.
+                if let (Expr::Var(addr), Some(&before)) = (&tested, body.last()) {
+                    let compare = self.instruction(before);
+                    let (_, write) = accesses(self.program, before, &compare);
+                    if matches!(compare.op, OpCode::LessThan | OpCode::Equals)
+                        && write == Some(*addr)
+                        && operand(self.program, ip, &instr, 1) != Expr::Var(*addr)
+                        && !self.live_after(start, *addr)
+                    {
+                        tested = value(self.program, before, &compare);
+                        body.pop();
+                    }
+                }
+                self.statements(&body, out);
+
+                // Falling through is the `then` branch.
+                let condition = tested.truth(instr.op == OpCode::JumpIfFalse);
+                let merge =
+                    Graph::immediate_post_dominator(&self.post_dominators, start).filter(|merge| {
+                        loops
+                            .last()
+                            .is_none_or(|(header, _)| self.loops[header].contains(merge))
+                    });
+                let mut then = Vec::new();
+                self.sequence(fall, merge, loops, &mut then);
+                let mut otherwise = Vec::new();
+                self.sequence(taken, merge, loops, &mut otherwise);
+                out.push(Stmt::If(condition, then, otherwise));
+                merge
+            }
+        }
+    }
+
+    fn statements(&self, ips: &[usize], out: &mut Vec<Stmt>) {
+        for &ip in ips {
+            let instr = self.instruction(ip);
+            // Jumps left inside a block are never taken.
+            if OpCode::jumps(instr.op) {
+                continue;
+            }
+            out.push(Stmt::Line(statement(self.program, ip, &instr)));
+        }
+    }
+
+    /// Drops unused labels and turns `if`s and loops into their neatest form.
+    fn simplify(&self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        for stmt in stmts {
+            match stmt {
+                Stmt::Label(block) if !self.gotos.contains(&block) => {}
+                Stmt::If(condition, then, otherwise) => {
+                    let then = self.simplify(then);
+                    let otherwise = self.simplify(otherwise);
+                    // Put a branch that jumps away first so the other can
+                    // follow the if rather than nest inside it.
+                    let swap = (then.is_empty() && !otherwise.is_empty())
+                        || (jumps_away(&otherwise)
+                            && (!jumps_away(&then) || otherwise.len() < then.len()));
+                    let (condition, then, otherwise) = if swap {
+                        (condition.negate(), otherwise, then)
+                    } else {
+                        (condition, then, otherwise)
+                    };
+                    if jumps_away(&then) {
+                        out.push(Stmt::If(condition, then, Vec::new()));
+                        out.extend(otherwise);
+                    } else {
+                        out.push(Stmt::If(condition, then, otherwise));
+                    }
+                }
+                Stmt::Loop(body) => {
+                    let mut body = self.simplify(body);
+                    if body.last() == Some(&Stmt::Continue) {
+                        body.pop();
+                    }
+                    out.push(match (body.first(), body.last()) {
+                        (Some(Stmt::If(condition, then, otherwise)), _)
+                            if then[..] == [Stmt::Break] && otherwise.is_empty() =>
+                        {
+                            let condition = condition.clone().negate();
+                            body.remove(0);
+                            Stmt::While(condition, body)
+                        }
+                        (_, Some(Stmt::If(condition, then, otherwise)))
+                            if then[..] == [Stmt::Break]
+                                && otherwise.is_empty()
+                                && !continues(&body) =>
+                        {
+                            let condition = condition.clone().negate();
+                            body.pop();
+                            Stmt::DoWhile(body, condition)
+                        }
+                        _ => Stmt::Loop(body),
+                    });
+                }
+                stmt => out.push(stmt),
+            }
+        }
+        out
+    }
+}
+
+fn print(stmts: &[Stmt], depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Line(line) => writeln!(out, "{}{}", indent, line),
+            Stmt::Label(block) => writeln!(out, "{}L{}:", indent, block),
+            Stmt::If(condition, then, otherwise) => {
+                writeln!(out, "{}if ({}) {{", indent, condition).unwrap();
+                print(then, depth + 1, out);
+                if !otherwise.is_empty() {
+                    writeln!(out, "{}}} else {{", indent).unwrap();
+                    print(otherwise, depth + 1, out);
+                }
+                writeln!(out, "{}}}", indent)
+            }
+            Stmt::Loop(body) => {
+                writeln!(out, "{}while (true) {{", indent).unwrap();
+                print(body, depth + 1, out);
+                writeln!(out, "{}}}", indent)
+            }
+            Stmt::While(condition, body) => {
+                writeln!(out, "{}while ({}) {{", indent, condition).unwrap();
+                print(body, depth + 1, out);
+                writeln!(out, "{}}}", indent)
+            }
+            Stmt::DoWhile(body, condition) => {
+                writeln!(out, "{}do {{", indent).unwrap();
+                print(body, depth + 1, out);
+                writeln!(out, "{}}} while ({})", indent, condition)
+            }
+            Stmt::Break => writeln!(out, "{}break", indent),
+            Stmt::Continue => writeln!(out, "{}continue", indent),
+            Stmt::Goto(block) => writeln!(out, "{}goto L{}", indent, block),
+            Stmt::Halt => writeln!(out, "{}halt", indent),
+            Stmt::NotDecompiled(what) => writeln!(out, "{}// not decompiled: {}", indent, what),
+        }
+        .unwrap();
+    }
+}
+
+/// Decompiles the code reachable from each entry point into a function named
+/// after it, preceded by the initial values of the cells they use.
+pub fn decompile(program: &[i32], entries: &[usize]) -> String {
+    let graph = Graph::build(program, entries);
+
+    let mut functions = String::new();
+    for &entry in entries {
+        let mut decompiler = Decompiler::new(program, &graph, entry);
+        let mut stmts = Vec::new();
+        decompiler.sequence(entry, None, &mut Vec::new(), &mut stmts);
+        let stmts = decompiler.simplify(stmts);
+
+        writeln!(functions, "\nfn entry_{}() {{", entry).unwrap();
+        print(&stmts, 1, &mut functions);
+        functions.push_str("}\n");
+    }
+
+    let mut cells = BTreeSet::new();
+    for block in graph.blocks.values() {
+        for &ip in &block.instructions {
+            let instr = decode_at(program, ip).unwrap();
+            let (reads, write) = accesses(program, ip, &instr);
+            cells.extend(reads.into_iter().chain(write));
+        }
+    }
+    let mut source = String::new();
+    for addr in cells {
+        match program.get(addr) {
+            Some(value) => writeln!(source, "var v{} = {}", addr, value).unwrap(),
+            None => writeln!(source, "var v{} // outside the program", addr).unwrap(),
+        }
+    }
+    source.push_str(&functions);
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_while_loop() {
+        // Outputs 0 to [18] - 1, then halts.
+        let program = [
+            7, 19, 18, 20, 1006, 20, 17, 4, 19, 1001, 19, 1, 19, 1105, 1, 0, 0, 99, 3, 0, 0,
+        ];
+        assert_eq!(
+            decompile(&program, &[0]),
+            "var v18 = 3\nvar v19 = 0\nvar v20 = 0\n\
+             \nfn entry_0() {\
+             \n    while (v19 < v18) {\
+             \n        output(v19)\
+             \n        v19 = v19 + 1\
+             \n    }\
+             \n    halt\
+             \n}\n"
+        );
+    }
+
+    #[test]
+    fn test_do_while_and_if_else() {
+        // Reads n, then for n down to 1 outputs whether n >= 3.
+        let program = [
+            3, 24, 1007, 24, 3, 25, 1006, 25, 14, 104, 0, 1105, 1, 16, 104, 1, 1001, 24, -1, 24,
+            1005, 24, 2, 99, 0, 0,
+        ];
+        assert_eq!(
+            decompile(&program, &[0]),
+            "var v24 = 0\nvar v25 = 0\n\
+             \nfn entry_0() {\
+             \n    v24 = input()\
+             \n    do {\
+             \n        if (v24 < 3) {\
+             \n            output(0)\
+             \n        } else {\
+             \n            output(1)\
+             \n        }\
+             \n        v24 = v24 - 1\
+             \n    } while (v24 != 0)\
+             \n    halt\
+             \n}\n"
+        );
+    }
+
+    #[test]
+    fn test_live_comparison_is_kept() {
+        // The comparison result in [12] is output after the jump.
+        let program = [1107, 1, 2, 12, 1006, 12, 9, 104, 5, 4, 12, 99, 0];
+        let source = decompile(&program, &[0]);
+        assert!(
+            source.contains(
+                "    v12 = 1 < 2\n    if (v12 != 0) {\n        output(5)\n    }\n    output(v12)\n"
+            ),
+            "{}",
+            source
+        );
+    }
+
+    #[test]
+    fn test_not_decompiled() {
+        let source = decompile(&[1101, 1, 98, 4, 4, 0, 99], &[0]);
+        assert!(source
+            .contains("v4 = 1 + 98\n    // not decompiled: code at 4 is modified at run time"));
+
+        let source = decompile(&[3, 6, 6, 6, 0, 99, 0], &[0]);
+        assert!(
+            source.contains(
+                "if (v6 == 0) {\n        // not decompiled: jump at 2 to v0\n    }\n    halt"
+            ),
+            "{}",
+            source
+        );
+    }
+}
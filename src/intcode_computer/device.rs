@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+/// A peripheral attached to a machine with `Machine::run_with`.
+pub trait Device {
+    /// Called with every word the machine outputs.
+    fn output(&mut self, value: i32);
+
+    /// Called when the machine wants input and its queue is empty. Returning
+    /// `None` pauses the machine until more input is available.
+    fn input(&mut self) -> Option<i32>;
+}
+
+/// Outputs go to the first device and input comes from the second, e.g. a
+/// `(TileDisplay, Joystick)` arcade cabinet.
+impl<A: Device, B: Device> Device for (A, B) {
+    fn output(&mut self, value: i32) {
+        self.0.output(value);
+    }
+
+    fn input(&mut self) -> Option<i32> {
+        self.1.input()
+    }
+}
+
+/// A 2D display drawn by (x, y, id) output triples.
+#[derive(Debug, Clone, Default)]
+pub struct TileDisplay {
+    tiles: HashMap<(i32, i32), i32>,
+    pending: Vec<i32>,
+    score_cell: Option<(i32, i32)>,
+    score: Option<i32>,
+}
+
+impl TileDisplay {
+    pub fn new() -> Self {
+        TileDisplay::default()
+    }
+
+    /// Triples addressed to `(x, y)` set the score instead of drawing a tile,
+    /// like the arcade cabinet's `(-1, 0)`.
+    pub fn with_score_cell(mut self, x: i32, y: i32) -> Self {
+        self.score_cell = Some((x, y));
+        self
+    }
+
+    pub fn score(&self) -> Option<i32> {
+        self.score
+    }
+
+    pub fn tile(&self, x: i32, y: i32) -> Option<i32> {
+        self.tiles.get(&(x, y)).copied()
+    }
+
+    pub fn tiles(&self) -> &HashMap<(i32, i32), i32> {
+        &self.tiles
+    }
+}
+
+impl Device for TileDisplay {
+    fn output(&mut self, value: i32) {
+        self.pending.push(value);
+        if let [x, y, id] = self.pending[..] {
+            if self.score_cell == Some((x, y)) {
+                self.score = Some(id);
+            } else {
+                self.tiles.insert((x, y), id);
+            }
+            self.pending.clear();
+        }
+    }
+
+    fn input(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+/// A joystick that reports its current tilt (-1 left, 0 neutral, 1 right)
+/// every time it's read.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Joystick {
+    pub tilt: i32,
+}
+
+impl Device for Joystick {
+    fn output(&mut self, _value: i32) {}
+
+    fn input(&mut self) -> Option<i32> {
+        Some(self.tilt)
+    }
+}
+
+/// A camera whose image is streamed as ASCII, one line per newline.
+#[derive(Debug, Clone, Default)]
+pub struct Camera {
+    image: Vec<String>,
+    line: String,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Camera::default()
+    }
+
+    pub fn image(&self) -> &[String] {
+        &self.image
+    }
+}
+
+impl Device for Camera {
+    fn output(&mut self, value: i32) {
+        match value as u8 as char {
+            '\n' if self.line.is_empty() => {}
+            '\n' => self.image.push(std::mem::take(&mut self.line)),
+            c => self.line.push(c),
+        }
+    }
+
+    fn input(&mut self) -> Option<i32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::{Machine, State};
+
+    #[test]
+    fn test_display_and_joystick() {
+        let mut program = vec![104, 1, 104, -2, 104, 3, 3, 20, 104, 0, 104, 0, 4, 20, 99];
+        program.resize(21, 0);
+
+        let mut cabinet = (TileDisplay::new(), Joystick { tilt: -1 });
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run_with(&mut cabinet), Ok(State::Halted));
+
+        let display = &cabinet.0;
+        assert_eq!(display.tile(1, -2), Some(3));
+        assert_eq!(display.tile(0, 0), Some(-1));
+        assert_eq!(display.tiles().len(), 2);
+    }
+
+    #[test]
+    fn test_camera() {
+        let program = vec![
+            104, 35, 104, 46, 104, 10, 104, 46, 104, 35, 104, 10, 104, 10, 99,
+        ];
+
+        let mut camera = Camera::new();
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run_with(&mut camera), Ok(State::Halted));
+        assert_eq!(camera.image(), ["#.", ".#"]);
+    }
+
+    #[test]
+    fn test_device_without_input() {
+        let mut machine = Machine::new(vec![3, 0, 99]);
+        assert_eq!(
+            machine.run_with(&mut TileDisplay::new()),
+            Ok(State::AwaitingInput)
+        );
+
+        machine.push_input(7);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.memory()[0], 7);
+    }
+}
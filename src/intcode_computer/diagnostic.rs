@@ -0,0 +1,164 @@
+use super::trace::trace_step;
+use super::{Machine, OpCode, State};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A value output by a diagnostic program, and where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub index: usize,
+    pub code: i32,
+    /// Address of the output instruction.
+    pub ip: usize,
+    pub instruction: String,
+    /// Address of the instruction that last wrote the output value, if it was
+    /// read from memory that the program itself had written.
+    pub source: Option<usize>,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "check {} output {} at ip {} ({})",
+            self.index, self.code, self.ip, self.instruction
+        )?;
+        if let Some(source) = self.source {
+            write!(f, ", written at ip {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a TEST-style diagnostic run: a 0 for every check that
+/// passed, followed by the diagnostic code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+    pub diagnostic: Check,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.code == 0)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| check.code != 0)
+    }
+
+    pub fn diagnostic_code(&self) -> i32 {
+        self.diagnostic.code
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failed = self.failures().count();
+        if failed == 0 {
+            writeln!(f, "{} checks passed", self.checks.len())?;
+        } else {
+            writeln!(f, "{} of {} checks failed:", failed, self.checks.len())?;
+            for check in self.failures() {
+                writeln!(f, "  {}", check)?;
+            }
+        }
+        write!(f, "diagnostic code {}", self.diagnostic.code)
+    }
+}
+
+/// Runs a diagnostic program to completion, attributing every output to the
+/// instruction that printed it and the one that computed it.
+pub fn diagnose(machine: &mut Machine) -> Result<Report, String> {
+    // Address written -> ip of the instruction that last wrote it.
+    let mut writers: HashMap<usize, usize> = HashMap::new();
+    let mut checks = Vec::new();
+
+    loop {
+        let ip = machine.instr_ptr;
+        let read_from = match machine.decode() {
+            Ok(decoded) if decoded.instr.op == OpCode::Output => decoded.reads[0].1,
+            _ => None,
+        };
+        let produced = machine.output.len();
+
+        let step = trace_step(machine);
+        if let Some((addr, _)) = step.write {
+            writers.insert(addr, ip);
+        }
+        for &code in &machine.output[produced..] {
+            checks.push(Check {
+                index: checks.len(),
+                code,
+                ip,
+                instruction: step.instruction.clone(),
+                source: read_from.and_then(|addr| writers.get(&addr).copied()),
+            });
+        }
+
+        match step.result {
+            Ok(State::Running) => {}
+            Ok(State::Halted) => break,
+            Ok(State::AwaitingInput) => return Err("ran out of input".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    let diagnostic = checks
+        .pop()
+        .ok_or_else(|| "halted without output".to_string())?;
+    Ok(Report { checks, diagnostic })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks that [13] + [14] == 5, outputting 0 if so, then outputs 99 as
+    // the diagnostic code.
+    fn program(a: i32, b: i32) -> Machine {
+        Machine::new(vec![
+            1, 13, 14, 15, 1001, 15, -5, 15, 4, 15, 104, 99, 99, a, b, 0,
+        ])
+    }
+
+    #[test]
+    fn test_passing_report() {
+        let report = diagnose(&mut program(2, 3)).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.diagnostic_code(), 99);
+        assert_eq!(report.diagnostic.ip, 10);
+        assert_eq!(report.to_string(), "1 checks passed\ndiagnostic code 99");
+    }
+
+    #[test]
+    fn test_failure_is_located() {
+        let report = diagnose(&mut program(2, 4)).unwrap();
+        assert!(!report.passed());
+
+        let failures: Vec<&Check> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 0);
+        assert_eq!(failures[0].code, 1);
+        assert_eq!(failures[0].ip, 8);
+        assert_eq!(failures[0].source, Some(4));
+        assert_eq!(
+            failures[0].to_string(),
+            "check 0 output 1 at ip 8 (out [15]), written at ip 4"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(diagnose(&mut Machine::new(vec![99])).is_err());
+        assert!(diagnose(&mut Machine::new(vec![3, 0, 99])).is_err());
+    }
+
+    #[test]
+    fn test_running_off_the_end() {
+        let error = diagnose(&mut Machine::new(vec![104, 0])).unwrap_err();
+        assert_eq!(error, "address 2 is outside memory at ip 2");
+    }
+}
@@ -0,0 +1,241 @@
+use super::{Machine, State};
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// A machine paused at an input prompt (or halted), with the inputs that led
+/// there from the root.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub path: Vec<i32>,
+    /// What the machine output in response to the last input.
+    pub output: Vec<i32>,
+    pub state: State,
+    pub machine: Machine,
+}
+
+/// What to do with a node once it's been reached.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Visit {
+    /// Try every input from here.
+    Continue,
+    /// Don't go any further down this branch.
+    Prune,
+    /// End the exploration, returning this node.
+    Stop,
+}
+
+/// Explores a program's state space by forking the machine at every input
+/// prompt and feeding each fork one of a fixed set of inputs, as for a
+/// droid choosing which way to move.
+///
+/// Forks share memory with their parent, so a branch costs only the pages it
+/// writes to. Runs that fail or exceed the step limit are dead ends.
+#[derive(Debug, Clone)]
+pub struct Explore {
+    root: Machine,
+    inputs: Vec<i32>,
+    max_depth: Option<usize>,
+    max_steps: Option<usize>,
+}
+
+impl Explore {
+    pub fn new(root: Machine) -> Self {
+        Explore {
+            root,
+            inputs: Vec::new(),
+            max_depth: None,
+            max_steps: None,
+        }
+    }
+
+    /// The inputs to try at every prompt, in order.
+    pub fn inputs(mut self, inputs: &[i32]) -> Self {
+        self.inputs = inputs.to_vec();
+        self
+    }
+
+    /// Don't expand nodes whose path is this long.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Gives up on a branch after this many instructions between prompts.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Runs a fork until it next needs input or halts.
+    fn advance(&self, mut machine: Machine, path: Vec<i32>) -> Option<Node> {
+        let start = machine.steps();
+        let state = loop {
+            if self
+                .max_steps
+                .is_some_and(|max| machine.steps() - start >= max)
+            {
+                return None;
+            }
+            match machine.step() {
+                Ok(State::Running) => {}
+                Ok(state) => break state,
+                Err(_) => return None,
+            }
+        };
+
+        Some(Node {
+            path,
+            output: machine.take_output(),
+            state,
+            machine,
+        })
+    }
+
+    /// Visits nodes in order of path length, so the first node stopped at is
+    /// one of the closest to the root. Nodes whose `key` has already been seen
+    /// are dropped before they're visited.
+    pub fn bfs<K, F, V>(&self, key: F, visit: V) -> Option<Node>
+    where
+        K: Hash + Eq,
+        F: FnMut(&Node) -> K,
+        V: FnMut(&Node) -> Visit,
+    {
+        self.explore(false, key, visit)
+    }
+
+    /// Visits nodes depth first, trying inputs in order. Nodes whose `key` has
+    /// already been seen are dropped before they're visited.
+    pub fn dfs<K, F, V>(&self, key: F, visit: V) -> Option<Node>
+    where
+        K: Hash + Eq,
+        F: FnMut(&Node) -> K,
+        V: FnMut(&Node) -> Visit,
+    {
+        self.explore(true, key, visit)
+    }
+
+    fn explore<K, F, V>(&self, depth_first: bool, mut key: F, mut visit: V) -> Option<Node>
+    where
+        K: Hash + Eq,
+        F: FnMut(&Node) -> K,
+        V: FnMut(&Node) -> Visit,
+    {
+        let mut seen = HashSet::new();
+        let mut pending = VecDeque::new();
+
+        let root = self.advance(self.root.clone(), Vec::new())?;
+        seen.insert(key(&root));
+        pending.push_back(root);
+
+        loop {
+            let node = if depth_first {
+                pending.pop_back()?
+            } else {
+                pending.pop_front()?
+            };
+
+            match visit(&node) {
+                Visit::Stop => return Some(node),
+                Visit::Prune => continue,
+                Visit::Continue => {}
+            }
+            if node.state == State::Halted
+                || self.max_depth.is_some_and(|max| node.path.len() >= max)
+            {
+                continue;
+            }
+
+            // Push in reverse when depth first so the first input is tried first.
+            let mut children = Vec::new();
+            for &value in &self.inputs {
+                let mut fork = node.machine.clone();
+                fork.push_input(value);
+                let mut path = node.path.clone();
+                path.push(value);
+
+                if let Some(child) = self.advance(fork, path) {
+                    if seen.insert(key(&child)) {
+                        children.push(child);
+                    }
+                }
+            }
+            if depth_first {
+                pending.extend(children.into_iter().rev());
+            } else {
+                pending.extend(children);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::memory::Memory;
+
+    // Adds every input to a running total in [21] and outputs it. The input
+    // cell is cleared afterwards so the whole memory is a good state key.
+    fn counter() -> Machine {
+        let mut program = vec![3, 20, 1, 20, 21, 21, 1101, 0, 0, 20, 4, 21, 1105, 1, 0];
+        program.resize(22, 0);
+        Machine::new(program)
+    }
+
+    fn state(node: &Node) -> (usize, Memory) {
+        (node.machine.instr_ptr(), node.machine.memory().clone())
+    }
+
+    fn reach_five(node: &Node) -> Visit {
+        match node.output.last() {
+            Some(5) => Visit::Stop,
+            Some(&total) if total > 5 => Visit::Prune,
+            _ => Visit::Continue,
+        }
+    }
+
+    #[test]
+    fn test_bfs_finds_shortest_path() {
+        let explore = Explore::new(counter()).inputs(&[1, 2]);
+        let found = explore.bfs(state, reach_five).unwrap();
+        assert_eq!(found.path, [1, 2, 2]);
+        assert_eq!(found.output, [5]);
+    }
+
+    #[test]
+    fn test_dfs() {
+        let explore = Explore::new(counter()).inputs(&[1, 2]);
+        let found = explore.dfs(|node| node.path.clone(), reach_five).unwrap();
+        assert_eq!(found.path, [1, 1, 1, 1, 1]);
+
+        let explore = Explore::new(counter()).inputs(&[2, 1]);
+        assert_eq!(explore.dfs(state, reach_five).unwrap().path, [2, 2, 1]);
+    }
+
+    #[test]
+    fn test_deduplication_and_depth() {
+        let mut visited = 0;
+        let explore = Explore::new(counter()).inputs(&[1, 2]).max_depth(4);
+        let found = explore.bfs(state, |_| {
+            visited += 1;
+            Visit::Continue
+        });
+        assert!(found.is_none());
+        // One node per total from 0 to 8.
+        assert_eq!(visited, 9);
+    }
+
+    #[test]
+    fn test_forks_share_memory() {
+        let mut program = counter().into_memory();
+        program.resize(1000, 0);
+        let root = Explore::new(Machine::new(program))
+            .bfs(state, |_| Visit::Stop)
+            .unwrap();
+
+        let mut fork = root.machine.clone();
+        fork.push_input(1);
+        fork.run().unwrap();
+        assert_eq!(fork.memory().shared_pages(root.machine.memory()), 15);
+    }
+}
@@ -0,0 +1,351 @@
+use super::Machine;
+
+/// A condition over machine state, e.g. `mem[225] > 100 && input_len == 0`.
+///
+/// Supports integer literals, `mem[expr]`, the variables `ip`, `steps`,
+/// `input_len`, `out_count` and `last_out`, the operators `+ - *`, comparisons,
+/// `&& || !` and parentheses. Comparisons and logical operators produce 0 or 1,
+/// and any nonzero value counts as true. Reads outside memory give 0, as does
+/// `last_out` before anything has been output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(i64),
+    Var(Var),
+    Mem(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Var {
+    Ip,
+    Steps,
+    InputLen,
+    OutCount,
+    LastOut,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?} in {}", token, text)),
+        }
+    }
+
+    pub fn eval(&self, machine: &Machine) -> i64 {
+        match self {
+            Expr::Literal(value) => *value,
+            Expr::Var(Var::Ip) => machine.instr_ptr as i64,
+            Expr::Var(Var::Steps) => machine.steps as i64,
+            Expr::Var(Var::InputLen) => machine.input.len() as i64,
+            Expr::Var(Var::OutCount) => machine.output.len() as i64,
+            Expr::Var(Var::LastOut) => machine.output.last().map_or(0, |&v| v as i64),
+            Expr::Mem(addr) => {
+                let addr = addr.eval(machine);
+                if addr < 0 {
+                    0
+                } else {
+                    machine.memory.get(addr as usize).map_or(0, |&v| v as i64)
+                }
+            }
+            Expr::Not(e) => (e.eval(machine) == 0) as i64,
+            Expr::Neg(e) => e.eval(machine).wrapping_neg(),
+            Expr::Binary(lhs, BinOp::And, rhs) => {
+                (lhs.eval(machine) != 0 && rhs.eval(machine) != 0) as i64
+            }
+            Expr::Binary(lhs, BinOp::Or, rhs) => {
+                (lhs.eval(machine) != 0 || rhs.eval(machine) != 0) as i64
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                let (a, b) = (lhs.eval(machine), rhs.eval(machine));
+                match op {
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Eq => (a == b) as i64,
+                    BinOp::Ne => (a != b) as i64,
+                    BinOp::Lt => (a < b) as i64,
+                    BinOp::Le => (a <= b) as i64,
+                    BinOp::Gt => (a > b) as i64,
+                    BinOp::Ge => (a >= b) as i64,
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, machine: &Machine) -> bool {
+        self.eval(machine) != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 16] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let value = rest[..len]
+                .parse()
+                .map_err(|e| format!("bad number {}: {}", &rest[..len], e))?;
+            tokens.push(Token::Number(value));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            len
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected character {:?} in {}", c, text))?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// How deeply an expression may nest, counting parentheses, `mem[...]`,
+/// unary operators and each operator in a chain, so that neither parsing nor
+/// evaluating it can overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("expression nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    fn next_op(&mut self, ops: &[(&str, BinOp)]) -> Option<BinOp> {
+        if let Some(Token::Op(token)) = self.tokens.get(self.pos) {
+            if let Some(&(_, op)) = ops.iter().find(|(text, _)| text == token) {
+                self.pos += 1;
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(token)) if *token == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            token => Err(format!("expected {} but found {:?}", op, token)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        operand: fn(&mut Parser) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut lhs = operand(self)?;
+        while let Some(op) = self.next_op(ops) {
+            self.enter()?;
+            let rhs = operand(self)?;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinOp::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinOp::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+            ],
+            Parser::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", BinOp::Add), ("-", BinOp::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(&[("*", BinOp::Mul)], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                self.enter()?;
+                let operand = self.unary()?;
+                self.depth -= 1;
+                Ok(Expr::Not(Box::new(operand)))
+            }
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                self.enter()?;
+                let operand = self.unary()?;
+                self.depth -= 1;
+                Ok(Expr::Neg(Box::new(operand)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of expression")?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(Expr::Literal(value)),
+            Token::Op("(") => {
+                self.enter()?;
+                let expr = self.or()?;
+                self.expect(")")?;
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "mem" => {
+                    self.expect("[")?;
+                    self.enter()?;
+                    let addr = self.or()?;
+                    self.expect("]")?;
+                    self.depth -= 1;
+                    Ok(Expr::Mem(Box::new(addr)))
+                }
+                "ip" => Ok(Expr::Var(Var::Ip)),
+                "steps" => Ok(Expr::Var(Var::Steps)),
+                "input_len" => Ok(Expr::Var(Var::InputLen)),
+                "out_count" => Ok(Expr::Var(Var::OutCount)),
+                "last_out" => Ok(Expr::Var(Var::LastOut)),
+                _ => Err(format!("unknown variable {}", name)),
+            },
+            token => Err(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, machine: &Machine) -> i64 {
+        Expr::parse(text).unwrap().eval(machine)
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut machine = Machine::new(vec![104, 5, 104, 7, 99, 150]);
+        machine.push_input(3);
+
+        assert_eq!(eval("mem[5] > 100", &machine), 1);
+        assert_eq!(eval("mem[1 + 2 * 2]", &machine), 150);
+        assert_eq!(eval("ip == 0 && input_len == 0", &machine), 0);
+        assert_eq!(eval("!(ip == 0) || input_len == 1", &machine), 1);
+        assert_eq!(eval("mem[-1] + mem[100]", &machine), 0);
+        assert_eq!(eval("-2 - -3", &machine), 1);
+
+        machine.run().unwrap();
+        assert_eq!(eval("out_count >= 2", &machine), 1);
+        assert_eq!(eval("last_out", &machine), 7);
+        assert_eq!(eval("steps", &machine), 2);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("").is_err());
+        assert!(Expr::parse("mem[1").is_err());
+        assert!(Expr::parse("ip ==").is_err());
+        assert!(Expr::parse("pc == 3").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("ip # 2").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let too_deep = Err("expression nested too deeply".to_string());
+        assert_eq!(Expr::parse(&"(".repeat(100_000)), too_deep);
+        assert_eq!(Expr::parse(&format!("{}1", "!-".repeat(50_000))), too_deep);
+        assert_eq!(
+            Expr::parse(&format!("{}1", "mem[".repeat(50_000))),
+            too_deep
+        );
+        assert_eq!(
+            Expr::parse(&format!("{}1", "1 + ".repeat(100_000))),
+            too_deep
+        );
+
+        let machine = Machine::new(vec![99]);
+        let nested = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        assert_eq!(eval(&format!("{} + {}", nested, nested), &machine), 2);
+        assert_eq!(eval(&format!("{}1", "1 + ".repeat(200)), &machine), 201);
+    }
+}
@@ -0,0 +1,259 @@
+//! C bindings for embedding a machine, declared in `include/intcode.h`.
+//!
+//! Machines are opaque pointers created by `intcode_new` and released with
+//! `intcode_free`. Outputs are queued on the handle and popped one at a time.
+//! A panic never unwinds into the caller: each entry point catches it and
+//! returns its failure value instead.
+
+use super::{Machine, State};
+
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+pub const INTCODE_OUTPUT: c_int = 0;
+pub const INTCODE_AWAITING_INPUT: c_int = 1;
+pub const INTCODE_HALTED: c_int = 2;
+pub const INTCODE_ERROR: c_int = -1;
+
+pub struct Handle {
+    machine: Machine,
+    output: VecDeque<i32>,
+    error: Option<CString>,
+}
+
+/// Runs `f`, returning `failed` if it panics.
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+/// Creates a machine running a copy of `len` words at `words`. Returns null
+/// if `words` is null and `len` isn't 0.
+///
+/// # Safety
+///
+/// `words` must point to `len` readable words.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_new(words: *const i32, len: usize) -> *mut Handle {
+    guard(ptr::null_mut(), || {
+        let program = if len == 0 {
+            Vec::new()
+        } else if words.is_null() {
+            return ptr::null_mut();
+        } else {
+            slice::from_raw_parts(words, len).to_vec()
+        };
+
+        Box::into_raw(Box::new(Handle {
+            machine: Machine::new(program),
+            output: VecDeque::new(),
+            error: None,
+        }))
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be null or come from `intcode_new`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_free(handle: *mut Handle) {
+    if !handle.is_null() {
+        guard((), || drop(Box::from_raw(handle)));
+    }
+}
+
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new`.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_push_input(handle: *mut Handle, value: i32) {
+    guard((), || (*handle).machine.push_input(value));
+}
+
+/// Runs until the machine outputs a value, needs input, halts or fails,
+/// returning which. After `INTCODE_ERROR`, `intcode_error` describes it.
+///
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new`.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_run(handle: *mut Handle) -> c_int {
+    let handle = &mut *handle;
+    handle.error = None;
+
+    let result = guard(None, || Some(run(handle)));
+    result.unwrap_or_else(|| {
+        handle.error = CString::new("the machine panicked").ok();
+        INTCODE_ERROR
+    })
+}
+
+fn run(handle: &mut Handle) -> c_int {
+    loop {
+        let result = handle.machine.step();
+        let produced = !handle.machine.output.is_empty();
+        handle.output.extend(handle.machine.output.drain(..));
+
+        match result {
+            Ok(State::Running) if produced => return INTCODE_OUTPUT,
+            Ok(State::Running) => {}
+            Ok(State::AwaitingInput) => return INTCODE_AWAITING_INPUT,
+            Ok(State::Halted) => return INTCODE_HALTED,
+            Err(e) => {
+                handle.error = CString::new(e.to_string()).ok();
+                return INTCODE_ERROR;
+            }
+        }
+    }
+}
+
+/// Pops the oldest queued output into `value`, returning 0 if there was none.
+///
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new` and `value` writable.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_pop_output(handle: *mut Handle, value: *mut i32) -> c_int {
+    guard(0, || match (*handle).output.pop_front() {
+        Some(v) => {
+            *value = v;
+            1
+        }
+        None => 0,
+    })
+}
+
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new`.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_memory_len(handle: *const Handle) -> usize {
+    guard(0, || (*handle).machine.memory.len())
+}
+
+/// Reads the word at `addr` into `value`, returning 0 if it's out of bounds.
+///
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new` and `value` writable.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_read(
+    handle: *const Handle,
+    addr: usize,
+    value: *mut i32,
+) -> c_int {
+    guard(0, || match (*handle).machine.memory.get(addr) {
+        Some(&v) => {
+            *value = v;
+            1
+        }
+        None => 0,
+    })
+}
+
+/// Writes `value` at `addr`, returning 0 if it's out of bounds.
+///
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new`.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_write(handle: *mut Handle, addr: usize, value: i32) -> c_int {
+    guard(0, || {
+        let memory = &mut (*handle).machine.memory;
+        if addr < memory.len() {
+            memory[addr] = value;
+            1
+        } else {
+            0
+        }
+    })
+}
+
+/// The message for the last `INTCODE_ERROR`, or null. It stays valid until the
+/// next `intcode_run` or `intcode_free`.
+///
+/// # Safety
+///
+/// `handle` must be a live machine from `intcode_new`.
+#[no_mangle]
+pub unsafe extern "C" fn intcode_error(handle: *const Handle) -> *const c_char {
+    guard(ptr::null(), || match &(*handle).error {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_run_until_events() {
+        let program = [3, 9, 4, 9, 104, 7, 99, 0, 0, 0];
+        unsafe {
+            let handle = intcode_new(program.as_ptr(), program.len());
+            assert_eq!(intcode_run(handle), INTCODE_AWAITING_INPUT);
+
+            intcode_push_input(handle, 42);
+            assert_eq!(intcode_run(handle), INTCODE_OUTPUT);
+            assert_eq!(intcode_run(handle), INTCODE_OUTPUT);
+            assert_eq!(intcode_run(handle), INTCODE_HALTED);
+
+            let mut value = 0;
+            assert_eq!(intcode_pop_output(handle, &mut value), 1);
+            assert_eq!(value, 42);
+            assert_eq!(intcode_pop_output(handle, &mut value), 1);
+            assert_eq!(value, 7);
+            assert_eq!(intcode_pop_output(handle, &mut value), 0);
+
+            assert_eq!(intcode_memory_len(handle), 10);
+            assert_eq!(intcode_write(handle, 8, 5), 1);
+            assert_eq!(intcode_read(handle, 8, &mut value), 1);
+            assert_eq!(value, 5);
+            assert_eq!(intcode_read(handle, 10, &mut value), 0);
+            assert_eq!(intcode_write(handle, 10, 5), 0);
+
+            intcode_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_error() {
+        let program = [42];
+        unsafe {
+            let handle = intcode_new(program.as_ptr(), program.len());
+            assert!(intcode_error(handle).is_null());
+            assert_eq!(intcode_run(handle), INTCODE_ERROR);
+            let message = CStr::from_ptr(intcode_error(handle));
+            assert_eq!(
+                message.to_str().unwrap(),
+                "1202 program error: unknown opcode 42 at ip 0"
+            );
+            intcode_free(handle);
+        }
+        assert!(unsafe { intcode_new(ptr::null(), 3) }.is_null());
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let cases: [(&[i32], &str); 3] = [
+            (&[1, 100, 0, 0, 99], "address 100 is outside memory at ip 0"),
+            (&[1105, 1, -5], "address -5 is outside memory at ip 0"),
+            (&[], "address 0 is outside memory at ip 0"),
+        ];
+        for (program, expected) in cases.iter() {
+            unsafe {
+                let handle = intcode_new(program.as_ptr(), program.len());
+                assert_eq!(intcode_run(handle), INTCODE_ERROR);
+                let message = CStr::from_ptr(intcode_error(handle));
+                assert_eq!(message.to_str().unwrap(), *expected);
+                intcode_free(handle);
+            }
+        }
+    }
+}
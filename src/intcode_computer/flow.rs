@@ -0,0 +1,429 @@
+//! The control flow graph of the code reachable from a set of entry points,
+//! found without running anything.
+//!
+//! Code is found by following fall-through and immediate jump targets.
+//! Jumps with an immediate condition are treated as always or never taken.
+//! Instructions the program writes to through a position mode parameter
+//! can't be trusted, so control reaching them ends in `Terminator::Unknown`,
+//! as it does at anything that doesn't decode. Writes through relative mode
+//! are assumed to stay clear of code.
+
+use super::{Instruction, OpCode, ParameterMode};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+/// How control leaves a block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Terminator {
+    /// Runs on into the block starting here.
+    Fall(usize),
+    /// An unconditional jump.
+    Goto(usize),
+    /// A conditional jump at `ip`, either to `taken` or on to `fall`.
+    Branch {
+        ip: usize,
+        taken: usize,
+        fall: usize,
+    },
+    /// A jump at `ip` whose target is only known at run time, and where
+    /// control goes if it's conditional and not taken.
+    Indirect {
+        ip: usize,
+        fall: Option<usize>,
+    },
+    Halt,
+    /// Control reaches this address, whose code can't be known ahead of
+    /// time: the program writes to it, it doesn't decode, or it's outside
+    /// the program.
+    Unknown(usize),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Terminator::Fall(next) | Terminator::Goto(next) => vec![next],
+            Terminator::Branch { taken, fall, .. } => vec![fall, taken],
+            Terminator::Indirect {
+                fall: Some(fall), ..
+            } => vec![fall],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A run of instructions entered only at the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// Addresses of the instructions in order, including a final jump or
+    /// halt. Empty for `Terminator::Unknown` blocks.
+    pub instructions: Vec<usize>,
+    pub end: Terminator,
+}
+
+#[derive(Debug, Clone)]
+pub struct Graph {
+    /// Blocks by start address. Every successor has a block.
+    pub blocks: BTreeMap<usize, Block>,
+    pub entries: Vec<usize>,
+}
+
+/// What an instruction does to the ip.
+enum Flow {
+    Next,
+    Jump(usize),
+    Branch(usize),
+    /// Whether the jump is conditional.
+    Indirect(bool),
+    Halt,
+}
+
+fn flow(program: &[i32], ip: usize, instr: &Instruction) -> Flow {
+    let operand = |i: usize| match instr.modes[i] {
+        ParameterMode::Immediate => Some(program[ip + 1 + i]),
+        _ => None,
+    };
+    let always = match instr.op {
+        OpCode::Halt => return Flow::Halt,
+        OpCode::JumpIfTrue => operand(0).map(|value| value != 0),
+        OpCode::JumpIfFalse => operand(0).map(|value| value == 0),
+        _ => return Flow::Next,
+    };
+    // Negative targets can't be code, so map them somewhere that isn't.
+    let target = operand(1).map(|target| usize::try_from(target).unwrap_or(usize::MAX));
+
+    match (always, target) {
+        (Some(false), _) => Flow::Next,
+        (always, None) => Flow::Indirect(always.is_none()),
+        (Some(true), Some(target)) => Flow::Jump(target),
+        (None, Some(target)) => Flow::Branch(target),
+    }
+}
+
+/// Decodes the instruction at `ip` if it lies wholly within the program.
+pub(super) fn decode_at(program: &[i32], ip: usize) -> Option<Instruction> {
+    match Instruction::decode(*program.get(ip)?, false) {
+        Ok(instr) if ip + OpCode::value_count(instr.op) <= program.len() => Some(instr),
+        _ => None,
+    }
+}
+
+/// Decodes everything reachable from `entries` without passing through an
+/// address in `modified`, returning it with the addresses it writes to.
+pub(super) fn find_code(
+    program: &[i32],
+    entries: &[usize],
+    modified: &BTreeSet<usize>,
+) -> (BTreeMap<usize, Instruction>, BTreeSet<usize>) {
+    let mut code = BTreeMap::new();
+    let mut writes = BTreeSet::new();
+    let mut pending = entries.to_vec();
+
+    while let Some(ip) = pending.pop() {
+        if code.contains_key(&ip) {
+            continue;
+        }
+        let instr = match decode_at(program, ip) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        let len = OpCode::value_count(instr.op);
+        if modified.range(ip..ip + len).next().is_some() {
+            continue;
+        }
+
+        if let Some(param) = OpCode::write_param(instr.op) {
+            if let ParameterMode::Position = instr.modes[param] {
+                if let Ok(addr) = usize::try_from(program[ip + 1 + param]) {
+                    writes.insert(addr);
+                }
+            }
+        }
+        match flow(program, ip, &instr) {
+            Flow::Next => pending.push(ip + len),
+            Flow::Jump(target) => pending.push(target),
+            Flow::Branch(target) => pending.extend(&[ip + len, target]),
+            Flow::Indirect(conditional) => {
+                if conditional {
+                    pending.push(ip + len);
+                }
+            }
+            Flow::Halt => {}
+        }
+        code.insert(ip, instr);
+    }
+
+    (code, writes)
+}
+
+impl Graph {
+    pub fn build(program: &[i32], entries: &[usize]) -> Graph {
+        // Anything written by code reachable at all is suspect, even if
+        // the writer turns out to be unreachable once that's taken into
+        // account.
+        let (_, modified) = find_code(program, entries, &BTreeSet::new());
+        let (code, _) = find_code(program, entries, &modified);
+
+        let mut leaders: BTreeSet<usize> = entries.iter().copied().collect();
+        for (&ip, instr) in &code {
+            let next = ip + OpCode::value_count(instr.op);
+            match flow(program, ip, instr) {
+                Flow::Next if !code.contains_key(&next) => {
+                    leaders.insert(next);
+                }
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                }
+                Flow::Branch(target) => {
+                    leaders.insert(target);
+                    leaders.insert(next);
+                }
+                Flow::Indirect(true) => {
+                    leaders.insert(next);
+                }
+                _ => {}
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut instructions = Vec::new();
+            let mut ip = start;
+            let end = loop {
+                let instr = match code.get(&ip) {
+                    Some(instr) => instr,
+                    None => break Terminator::Unknown(ip),
+                };
+                instructions.push(ip);
+                let next = ip + OpCode::value_count(instr.op);
+                match flow(program, ip, instr) {
+                    Flow::Next if leaders.contains(&next) => break Terminator::Fall(next),
+                    Flow::Next => ip = next,
+                    Flow::Jump(target) => break Terminator::Goto(target),
+                    Flow::Branch(taken) => {
+                        break Terminator::Branch {
+                            ip,
+                            taken,
+                            fall: next,
+                        }
+                    }
+                    Flow::Indirect(conditional) => {
+                        break Terminator::Indirect {
+                            ip,
+                            fall: Some(next).filter(|_| conditional),
+                        }
+                    }
+                    Flow::Halt => break Terminator::Halt,
+                }
+            };
+            blocks.insert(
+                start,
+                Block {
+                    start,
+                    instructions,
+                    end,
+                },
+            );
+        }
+
+        Graph {
+            blocks,
+            entries: entries.to_vec(),
+        }
+    }
+
+    pub fn successors(&self, block: usize) -> Vec<usize> {
+        self.blocks[&block].end.successors()
+    }
+
+    /// Blocks reachable from `entry`, including itself.
+    pub fn reachable(&self, entry: usize) -> BTreeSet<usize> {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![entry];
+        while let Some(block) = pending.pop() {
+            if seen.insert(block) {
+                pending.extend(self.successors(block));
+            }
+        }
+        seen
+    }
+
+    /// For every block reachable from `entry`, the blocks every path from
+    /// `entry` to it passes through, including itself.
+    pub fn dominators(&self, entry: usize) -> BTreeMap<usize, BTreeSet<usize>> {
+        let reachable = self.reachable(entry);
+        let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &block in &reachable {
+            for next in self.successors(block) {
+                predecessors.entry(next).or_default().push(block);
+            }
+        }
+
+        let mut dominators: BTreeMap<usize, BTreeSet<usize>> = reachable
+            .iter()
+            .map(|&block| (block, reachable.clone()))
+            .collect();
+        dominators.insert(entry, Some(entry).into_iter().collect());
+        fixed_point(&reachable, &mut dominators, |block| {
+            if block == entry {
+                return Vec::new();
+            }
+            predecessors.get(&block).cloned().unwrap_or_default()
+        });
+        dominators
+    }
+
+    /// For every block reachable from `entry`, the blocks every path from it
+    /// to a halt (or any other exit from the graph) passes through, including
+    /// itself. Blocks that can never leave the graph only post-dominate
+    /// themselves.
+    pub fn post_dominators(&self, entry: usize) -> BTreeMap<usize, BTreeSet<usize>> {
+        let reachable = self.reachable(entry);
+
+        let mut leaving: BTreeSet<usize> = reachable
+            .iter()
+            .copied()
+            .filter(|&block| self.successors(block).is_empty())
+            .collect();
+        loop {
+            let more: Vec<usize> = reachable
+                .iter()
+                .copied()
+                .filter(|block| !leaving.contains(block))
+                .filter(|&block| self.successors(block).iter().any(|s| leaving.contains(s)))
+                .collect();
+            if more.is_empty() {
+                break;
+            }
+            leaving.extend(more);
+        }
+
+        let mut post_dominators: BTreeMap<usize, BTreeSet<usize>> = reachable
+            .iter()
+            .map(|&block| {
+                if leaving.contains(&block) && !self.successors(block).is_empty() {
+                    (block, leaving.clone())
+                } else {
+                    (block, Some(block).into_iter().collect())
+                }
+            })
+            .collect();
+        fixed_point(&leaving, &mut post_dominators, |block| {
+            self.successors(block)
+                .into_iter()
+                .filter(|s| leaving.contains(s))
+                .collect()
+        });
+        post_dominators
+    }
+
+    /// The nearest block other than `block` that every path from it to an
+    /// exit passes through, given `post_dominators` from the same entry.
+    pub fn immediate_post_dominator(
+        post_dominators: &BTreeMap<usize, BTreeSet<usize>>,
+        block: usize,
+    ) -> Option<usize> {
+        let own = &post_dominators[&block];
+        own.iter()
+            .copied()
+            .find(|&other| other != block && post_dominators[&other].len() == own.len() - 1)
+    }
+}
+
+/// Iterates `sets[block] = {block} ∪ ⋂ sets[neighbour]` until nothing
+/// changes. Blocks without neighbours keep their starting set.
+fn fixed_point<F>(
+    blocks: &BTreeSet<usize>,
+    sets: &mut BTreeMap<usize, BTreeSet<usize>>,
+    neighbours: F,
+) where
+    F: Fn(usize) -> Vec<usize>,
+{
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in blocks {
+            let mut neighbours = neighbours(block).into_iter();
+            let first = match neighbours.next() {
+                Some(first) => sets[&first].clone(),
+                None => continue,
+            };
+            let mut set = neighbours.fold(first, |set, other| {
+                set.intersection(&sets[&other]).copied().collect()
+            });
+            set.insert(block);
+            if set != sets[&block] {
+                sets.insert(block, set);
+                changed = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts [14] down from 3, outputting it each time round.
+    const COUNTDOWN: [i32; 15] = [4, 14, 1001, 14, -1, 14, 1005, 14, 0, 104, -1, 99, 0, 0, 3];
+
+    #[test]
+    fn test_blocks() {
+        let graph = Graph::build(&COUNTDOWN, &[0]);
+        let starts: Vec<usize> = graph.blocks.keys().copied().collect();
+        assert_eq!(starts, [0, 9]);
+        assert_eq!(graph.blocks[&0].instructions, [0, 2, 6]);
+        assert_eq!(
+            graph.blocks[&0].end,
+            Terminator::Branch {
+                ip: 6,
+                taken: 0,
+                fall: 9
+            }
+        );
+        assert_eq!(graph.blocks[&9].end, Terminator::Halt);
+
+        let dominators = graph.dominators(0);
+        assert!(dominators[&9].contains(&0));
+        let post_dominators = graph.post_dominators(0);
+        assert_eq!(
+            Graph::immediate_post_dominator(&post_dominators, 0),
+            Some(9)
+        );
+    }
+
+    #[test]
+    fn test_constant_and_indirect_jumps() {
+        // jt 1, 6 skips over an indirect jump through [0], which the
+        // conditional jump at 6 leads back to.
+        let program = [1105, 1, 6, 5, 0, 0, 1006, 0, 3, 99];
+        let graph = Graph::build(&program, &[0]);
+        assert_eq!(graph.blocks[&0].end, Terminator::Goto(6));
+        assert_eq!(
+            graph.blocks[&6].end,
+            Terminator::Branch {
+                ip: 6,
+                taken: 3,
+                fall: 9
+            }
+        );
+        assert_eq!(
+            graph.blocks[&3].end,
+            Terminator::Indirect {
+                ip: 3,
+                fall: Some(6)
+            }
+        );
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // Turns the output at 4 into a halt before running it.
+        let program = [1101, 1, 98, 4, 4, 0, 99];
+        let graph = Graph::build(&program, &[0]);
+        assert_eq!(graph.blocks[&0].end, Terminator::Fall(4));
+        assert_eq!(graph.blocks[&4].end, Terminator::Unknown(4));
+        assert!(graph.blocks[&4].instructions.is_empty());
+    }
+}
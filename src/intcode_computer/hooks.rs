@@ -0,0 +1,266 @@
+//! Callbacks for everything a machine does, run by `Machine::run_hooked`.
+//!
+//! Every event but `AfterInstruction` fires before the instruction changes
+//! anything, so aborting from one leaves the machine exactly as it was: the
+//! same instruction runs again, events and all, when it's resumed.
+
+use super::{Machine, State};
+
+/// Something an instruction is about to do, or has just done.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// The instruction at the ip is about to be decoded and run.
+    BeforeInstruction,
+    /// The instruction at `ip` ran; the machine has moved on from it.
+    AfterInstruction {
+        ip: usize,
+    },
+    /// A position or relative mode parameter read `value` from `addr`.
+    /// Immediate parameters and the instruction's own words aren't reported.
+    Read {
+        addr: usize,
+        value: i32,
+    },
+    /// `value` is about to be stored at `addr`.
+    Write {
+        addr: usize,
+        value: i32,
+    },
+    /// An input instruction is about to take a value, whether or not the
+    /// queue has one.
+    InputRequested,
+    Output(i32),
+    /// The halt instruction ran.
+    Halt,
+}
+
+/// What a callback wants to happen next.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Control {
+    Continue,
+    Abort,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Stop {
+    /// A callback aborted the run at this event.
+    Aborted(Event),
+    /// The machine halted or needs input, or for a single step, ran an
+    /// instruction.
+    Machine(State),
+}
+
+type Callback<'a> = Box<dyn FnMut(&Machine) -> Control + 'a>;
+type AddressCallback<'a> = Box<dyn FnMut(&Machine, usize, i32) -> Control + 'a>;
+type ValueCallback<'a> = Box<dyn FnMut(&Machine, i32) -> Control + 'a>;
+type IpCallback<'a> = Box<dyn FnMut(&Machine, usize) -> Control + 'a>;
+
+/// Callbacks by event, run in the order they were added. The first one to
+/// abort stops the run, and later ones for the same event don't run.
+#[derive(Default)]
+pub struct Hooks<'a> {
+    before: Vec<Callback<'a>>,
+    after: Vec<IpCallback<'a>>,
+    read: Vec<AddressCallback<'a>>,
+    write: Vec<AddressCallback<'a>>,
+    input: Vec<Callback<'a>>,
+    output: Vec<ValueCallback<'a>>,
+    halt: Vec<Callback<'a>>,
+}
+
+impl<'a> Hooks<'a> {
+    pub fn new() -> Self {
+        Hooks::default()
+    }
+
+    /// Called with the machine at the instruction about to run.
+    pub fn on_before_instruction<F: FnMut(&Machine) -> Control + 'a>(&mut self, f: F) {
+        self.before.push(Box::new(f));
+    }
+
+    /// Called with the address of the instruction that just ran.
+    pub fn on_after_instruction<F: FnMut(&Machine, usize) -> Control + 'a>(&mut self, f: F) {
+        self.after.push(Box::new(f));
+    }
+
+    /// Called with the address and value of each memory read.
+    pub fn on_read<F: FnMut(&Machine, usize, i32) -> Control + 'a>(&mut self, f: F) {
+        self.read.push(Box::new(f));
+    }
+
+    /// Called with the address and value of each memory write.
+    pub fn on_write<F: FnMut(&Machine, usize, i32) -> Control + 'a>(&mut self, f: F) {
+        self.write.push(Box::new(f));
+    }
+
+    pub fn on_input_requested<F: FnMut(&Machine) -> Control + 'a>(&mut self, f: F) {
+        self.input.push(Box::new(f));
+    }
+
+    pub fn on_output<F: FnMut(&Machine, i32) -> Control + 'a>(&mut self, f: F) {
+        self.output.push(Box::new(f));
+    }
+
+    pub fn on_halt<F: FnMut(&Machine) -> Control + 'a>(&mut self, f: F) {
+        self.halt.push(Box::new(f));
+    }
+
+    pub(super) fn fire(&mut self, machine: &Machine, event: Event) -> Control {
+        let abort = match event {
+            Event::BeforeInstruction => {
+                self.before.iter_mut().any(|f| f(machine) == Control::Abort)
+            }
+            Event::AfterInstruction { ip } => self
+                .after
+                .iter_mut()
+                .any(|f| f(machine, ip) == Control::Abort),
+            Event::Read { addr, value } => self
+                .read
+                .iter_mut()
+                .any(|f| f(machine, addr, value) == Control::Abort),
+            Event::Write { addr, value } => self
+                .write
+                .iter_mut()
+                .any(|f| f(machine, addr, value) == Control::Abort),
+            Event::InputRequested => self.input.iter_mut().any(|f| f(machine) == Control::Abort),
+            Event::Output(value) => self
+                .output
+                .iter_mut()
+                .any(|f| f(machine, value) == Control::Abort),
+            Event::Halt => self.halt.iter_mut().any(|f| f(machine) == Control::Abort),
+        };
+        if abort {
+            Control::Abort
+        } else {
+            Control::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_events_in_order() {
+        // in [9]; add [9],3 -> [10]; out [10]; halt
+        let mut machine = Machine::new(vec![3, 9, 1001, 9, 3, 10, 4, 10, 99, 0, 0]);
+        machine.push_input(4);
+        let events = RefCell::new(Vec::new());
+        let mut hooks = Hooks::new();
+        hooks.on_before_instruction(|m| {
+            events
+                .borrow_mut()
+                .push(format!("before {}", m.instr_ptr()));
+            Control::Continue
+        });
+        hooks.on_after_instruction(|_, ip| {
+            events.borrow_mut().push(format!("after {}", ip));
+            Control::Continue
+        });
+        hooks.on_read(|_, addr, value| {
+            events
+                .borrow_mut()
+                .push(format!("read [{}] = {}", addr, value));
+            Control::Continue
+        });
+        hooks.on_write(|_, addr, value| {
+            events
+                .borrow_mut()
+                .push(format!("write [{}] = {}", addr, value));
+            Control::Continue
+        });
+        hooks.on_input_requested(|_| {
+            events.borrow_mut().push("input".to_string());
+            Control::Continue
+        });
+        hooks.on_output(|_, value| {
+            events.borrow_mut().push(format!("output {}", value));
+            Control::Continue
+        });
+        hooks.on_halt(|_| {
+            events.borrow_mut().push("halt".to_string());
+            Control::Continue
+        });
+
+        assert_eq!(
+            machine.run_hooked(&mut hooks),
+            Ok(Stop::Machine(State::Halted))
+        );
+        drop(hooks);
+        assert_eq!(
+            events.into_inner(),
+            [
+                "before 0",
+                "input",
+                "write [9] = 4",
+                "after 0",
+                "before 2",
+                "read [9] = 4",
+                "write [10] = 7",
+                "after 2",
+                "before 6",
+                "read [10] = 7",
+                "output 7",
+                "after 6",
+                "before 8",
+                "halt",
+            ]
+        );
+        assert_eq!(machine.output(), [7]);
+    }
+
+    #[test]
+    fn test_abort_leaves_instruction_to_run_again() {
+        let mut machine = Machine::new(vec![1101, 2, 3, 5, 99, 0]);
+        let mut writes = 0;
+        let mut hooks = Hooks::new();
+        hooks.on_write(|_, _, _| {
+            writes += 1;
+            if writes == 1 {
+                Control::Abort
+            } else {
+                Control::Continue
+            }
+        });
+
+        assert_eq!(
+            machine.run_hooked(&mut hooks),
+            Ok(Stop::Aborted(Event::Write { addr: 5, value: 5 }))
+        );
+        assert_eq!((machine.instr_ptr(), machine.steps()), (0, 0));
+        assert_eq!(machine.memory()[5], 0);
+
+        assert_eq!(
+            machine.run_hooked(&mut hooks),
+            Ok(Stop::Machine(State::Halted))
+        );
+        drop(hooks);
+        assert_eq!(writes, 2);
+        assert_eq!(machine.memory()[5], 5);
+    }
+
+    #[test]
+    fn test_coverage_and_awaiting_input() {
+        let mut machine = Machine::new(vec![3, 0, 99]);
+        let mut covered = Vec::new();
+        let mut hooks = Hooks::new();
+        hooks.on_after_instruction(|_, ip| {
+            covered.push(ip);
+            Control::Continue
+        });
+        assert_eq!(
+            machine.run_hooked(&mut hooks),
+            Ok(Stop::Machine(State::AwaitingInput))
+        );
+        machine.push_input(99);
+        assert_eq!(
+            machine.run_hooked(&mut hooks),
+            Ok(Stop::Machine(State::Halted))
+        );
+        drop(hooks);
+        assert_eq!(covered, [0]);
+    }
+}
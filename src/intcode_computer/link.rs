@@ -0,0 +1,333 @@
+//! Relocatable modules, and a linker that lays them out one after another
+//! into a single program.
+//!
+//! Module files look like:
+//!
+//! ```text
+//! # outputs the value at arg, then jumps to the address in ret
+//! code: 4,5,106,0,6,0,0
+//! relocations: 1,4
+//! exports: print@0, arg@5, ret@6
+//! ```
+//!
+//! Every key is optional. `relocations` are the offsets of words holding
+//! addresses within the module, which get the module's load address added.
+//! `imports` (also `symbol@offset`) are words that get the address of a
+//! symbol exported by any of the linked modules added, so a word of 1 with
+//! an import of `buffer` refers to the word after `buffer`. Lines starting
+//! with `#` are comments.
+
+use super::parse_program;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A name and an offset into a module's code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub offset: usize,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.offset)
+    }
+}
+
+/// Code assembled as if it were loaded at address 0, with the fixups needed
+/// to load it anywhere else.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub name: String,
+    pub code: Vec<i32>,
+    pub relocations: Vec<usize>,
+    pub exports: Vec<Symbol>,
+    pub imports: Vec<Symbol>,
+}
+
+impl Module {
+    pub fn new(name: &str, code: Vec<i32>) -> Self {
+        Module {
+            name: name.to_string(),
+            code,
+            ..Module::default()
+        }
+    }
+
+    /// Marks words as holding addresses within this module.
+    pub fn relocate(mut self, offsets: &[usize]) -> Self {
+        self.relocations.extend_from_slice(offsets);
+        self
+    }
+
+    pub fn export(mut self, name: &str, offset: usize) -> Self {
+        self.exports.push(Symbol {
+            name: name.to_string(),
+            offset,
+        });
+        self
+    }
+
+    /// Adds the address of `name` to the word at `offset` when linked.
+    pub fn import(mut self, name: &str, offset: usize) -> Self {
+        self.imports.push(Symbol {
+            name: name.to_string(),
+            offset,
+        });
+        self
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<Module, String> {
+        let mut module = Module::new(name, Vec::new());
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap().trim();
+            let value = parts
+                .next()
+                .ok_or(format!("expected key: value, got {}", line))?
+                .trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            match key {
+                "code" => {
+                    module.code = parse_program(value).map_err(|e| format!("{}: {}", line, e))?
+                }
+                "relocations" => {
+                    for offset in value.split(',') {
+                        module.relocations.push(
+                            offset
+                                .trim()
+                                .parse()
+                                .map_err(|e| format!("bad relocation {}: {}", offset, e))?,
+                        );
+                    }
+                }
+                "exports" => module.exports.extend(parse_symbols(value)?),
+                "imports" => module.imports.extend(parse_symbols(value)?),
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Loads a module file, naming it after the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Module> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Module::parse(&name, &fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Checks that every fixup is within the code. Exports may point just
+    /// past the end, at whatever module follows.
+    fn check(&self) -> Result<(), String> {
+        let len = self.code.len();
+        let fixups = self
+            .relocations
+            .iter()
+            .map(|&offset| (offset, "relocation"))
+            .chain(self.imports.iter().map(|import| (import.offset, "import")));
+        for (offset, kind) in fixups {
+            if offset >= len {
+                return Err(format!(
+                    "{}: {} at {} is outside its {} words",
+                    self.name, kind, offset, len
+                ));
+            }
+        }
+        for export in &self.exports {
+            if export.offset > len {
+                return Err(format!(
+                    "{}: export {} is outside its {} words",
+                    self.name, export, len
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join<T: ToString>(items: &[T]) -> String {
+            let items: Vec<String> = items.iter().map(T::to_string).collect();
+            items.join(",")
+        }
+
+        writeln!(f, "# {}", self.name)?;
+        writeln!(f, "code: {}", join(&self.code))?;
+        writeln!(f, "relocations: {}", join(&self.relocations))?;
+        writeln!(f, "exports: {}", join(&self.exports))?;
+        writeln!(f, "imports: {}", join(&self.imports))
+    }
+}
+
+fn parse_symbols(text: &str) -> Result<Vec<Symbol>, String> {
+    text.split(',')
+        .map(|symbol| {
+            let mut parts = symbol.trim().splitn(2, '@');
+            let name = parts.next().unwrap();
+            let offset = parts.next().map(str::parse::<usize>);
+            match offset {
+                Some(Ok(offset)) if !name.is_empty() => Ok(Symbol {
+                    name: name.to_string(),
+                    offset,
+                }),
+                _ => Err(format!(
+                    "bad symbol {}, expected name@offset",
+                    symbol.trim()
+                )),
+            }
+        })
+        .collect()
+}
+
+/// A linked program, with where each exported symbol ended up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub program: Vec<i32>,
+    pub symbols: BTreeMap<String, usize>,
+}
+
+/// Loads the modules one after another from address 0, so the first one is
+/// the entry point, and applies their fixups.
+pub fn link(modules: &[Module]) -> Result<Image, String> {
+    let mut symbols = BTreeMap::new();
+    let mut exporters = BTreeMap::new();
+    let mut bases = Vec::new();
+    let mut len = 0;
+
+    for module in modules {
+        module.check()?;
+        for export in &module.exports {
+            if let Some(other) = exporters.insert(&export.name, &module.name) {
+                return Err(format!(
+                    "{} is exported by both {} and {}",
+                    export.name, other, module.name
+                ));
+            }
+            symbols.insert(export.name.clone(), len + export.offset);
+        }
+        bases.push(len);
+        len += module.code.len();
+    }
+
+    let address = |addr: usize| {
+        i32::try_from(addr).map_err(|_| format!("address {} doesn't fit in a word", addr))
+    };
+    let mut program = Vec::with_capacity(len);
+    for (module, base) in modules.iter().zip(bases) {
+        let mut code = module.code.clone();
+        let fixups = module
+            .relocations
+            .iter()
+            .map(|&offset| Ok((offset, base)))
+            .chain(
+                module
+                    .imports
+                    .iter()
+                    .map(|import| match symbols.get(&import.name) {
+                        Some(&addr) => Ok((import.offset, addr)),
+                        None => Err(format!(
+                            "{} imports undefined symbol {}",
+                            module.name, import.name
+                        )),
+                    }),
+            );
+        for fixup in fixups {
+            let (offset, addr) = fixup?;
+            code[offset] = code[offset]
+                .checked_add(address(addr)?)
+                .ok_or(format!("{}: fixup at {} overflows", module.name, offset))?;
+        }
+        program.extend(code);
+    }
+
+    Ok(Image { program, symbols })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::{Machine, State};
+
+    // Stores 42 in print's argument and the address of its own halt in
+    // print's return address, then jumps to print.
+    fn main_module() -> Module {
+        Module::new("main", vec![1101, 42, 0, 0, 1101, 11, 0, 0, 1105, 1, 0, 99])
+            .relocate(&[5])
+            .import("arg", 3)
+            .import("ret", 7)
+            .import("print", 10)
+    }
+
+    fn print_module() -> Module {
+        Module::parse(
+            "print",
+            "# outputs arg, then returns\n\
+             code: 4,5,106,0,6,0,0\n\
+             relocations: 1,4\n\
+             exports: print@0, arg@5, ret@6\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let image = link(&[main_module(), print_module()]).unwrap();
+        assert_eq!(
+            image.program,
+            [1101, 42, 0, 17, 1101, 11, 0, 18, 1105, 1, 12, 99, 4, 17, 106, 0, 18, 0, 0]
+        );
+        assert_eq!(image.symbols["print"], 12);
+
+        let mut machine = Machine::new(image.program);
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.output(), [42]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            link(&[main_module()]),
+            Err("main imports undefined symbol arg".to_string())
+        );
+        assert_eq!(
+            link(&[main_module(), print_module(), print_module()]),
+            Err("print is exported by both print and print".to_string())
+        );
+        assert_eq!(
+            link(&[Module::new("bad", vec![99]).relocate(&[1])]),
+            Err("bad: relocation at 1 is outside its 1 words".to_string())
+        );
+
+        assert!(Module::parse("m", "code: 99\nexports: start").is_err());
+        assert!(Module::parse("m", "entry: 0").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let module = main_module().export("main", 0);
+        assert_eq!(Module::parse("main", &module.to_string()), Ok(module));
+        assert_eq!(
+            Module::parse("empty", "# nothing"),
+            Ok(Module::new("empty", vec![]))
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use super::disassemble;
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
+
+const PAGE_SIZE: usize = 64;
+
+type Page = [i32; PAGE_SIZE];
+
+/// Machine memory, split into pages that are shared between clones and only
+/// copied when one of them writes to a page. Cloning a machine is therefore
+/// cheap however large its program, which is what makes forking it at every
+/// input prompt affordable.
+///
+/// Indexes like a slice, panicking on out of bounds addresses.
+#[derive(Clone, Default)]
+pub struct Memory {
+    pages: Vec<Arc<Page>>,
+    len: usize,
+}
+
+impl Memory {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, addr: usize) -> Option<&i32> {
+        if addr < self.len {
+            Some(&self.pages[addr / PAGE_SIZE][addr % PAGE_SIZE])
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &i32> + '_ {
+        self.pages
+            .iter()
+            .flat_map(|page| page.iter())
+            .take(self.len)
+    }
+
+    pub fn to_vec(&self) -> Vec<i32> {
+        self.iter().copied().collect()
+    }
+
+    /// Same as `disassemble` on the equivalent slice.
+    pub fn disassemble(&self, ptr: usize) -> (String, usize) {
+        let window: Vec<i32> = (ptr..self.len.min(ptr.saturating_add(4)))
+            .map(|a| self[a])
+            .collect();
+        disassemble(&window, 0)
+    }
+
+    /// How many pages are still shared with `other`, i.e. haven't been copied
+    /// by a write since one was cloned from the other.
+    pub fn shared_pages(&self, other: &Memory) -> usize {
+        self.pages
+            .iter()
+            .zip(&other.pages)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl From<Vec<i32>> for Memory {
+    fn from(words: Vec<i32>) -> Self {
+        let pages = words
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                let mut page = [0; PAGE_SIZE];
+                page[..chunk.len()].copy_from_slice(chunk);
+                Arc::new(page)
+            })
+            .collect();
+        Memory {
+            pages,
+            len: words.len(),
+        }
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = i32;
+
+    fn index(&self, addr: usize) -> &i32 {
+        match self.get(addr) {
+            Some(value) => value,
+            None => panic!("address {} out of bounds of {} words", addr, self.len),
+        }
+    }
+}
+
+impl IndexMut<usize> for Memory {
+    fn index_mut(&mut self, addr: usize) -> &mut i32 {
+        if addr >= self.len {
+            panic!("address {} out of bounds of {} words", addr, self.len);
+        }
+        &mut Arc::make_mut(&mut self.pages[addr / PAGE_SIZE])[addr % PAGE_SIZE]
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        self.len == other.len
+            && self
+                .pages
+                .iter()
+                .zip(&other.pages)
+                .all(|(a, b)| Arc::ptr_eq(a, b) || a == b)
+    }
+}
+
+impl Eq for Memory {}
+
+impl PartialEq<[i32]> for Memory {
+    fn eq(&self, other: &[i32]) -> bool {
+        self.len == other.len() && self.iter().eq(other)
+    }
+}
+
+impl Hash for Memory {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len.hash(state);
+        for value in self.iter() {
+            value.hash(state);
+        }
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut memory = Memory::from((0..200).collect::<Vec<i32>>());
+        let fork = memory.clone();
+        assert_eq!(memory.shared_pages(&fork), 4);
+
+        memory[70] = -1;
+        assert_eq!(memory.shared_pages(&fork), 3);
+        assert_eq!(memory[70], -1);
+        assert_eq!(fork[70], 70);
+        assert_ne!(memory, fork);
+
+        memory[70] = 70;
+        assert_eq!(memory, fork);
+        assert_eq!(memory, *(0..200).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(memory.len(), 3);
+        assert_eq!(memory.get(2), Some(&3));
+        assert_eq!(memory.get(3), None);
+        assert_eq!(memory.to_vec(), [1, 2, 3]);
+        assert_eq!(format!("{:?}", memory), "[1, 2, 3]");
+    }
+
+    #[test]
+    #[should_panic(expected = "address 3 out of bounds")]
+    fn test_write_out_of_bounds() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        memory[3] = 4;
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let memory = Memory::from(vec![1101, 2, 3, 4, 99]);
+        assert_eq!(memory.disassemble(0), ("add 2, 3, [4]".to_string(), 4));
+        assert_eq!(memory.disassemble(4), ("hlt".to_string(), 1));
+        assert_eq!(Memory::from(vec![1, 2]).disassemble(0).0, "data 1");
+        assert_eq!(memory.disassemble(5), ("<out of bounds>".to_string(), 0));
+    }
+}
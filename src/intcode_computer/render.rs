@@ -0,0 +1,192 @@
+use super::device::TileDisplay;
+
+use std::collections::HashMap;
+
+pub type Rgb = [u8; 3];
+
+/// Maps tile ids to the character and colour they're drawn with.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    tiles: HashMap<i32, (char, Rgb)>,
+    background: (char, Rgb),
+    unknown: (char, Rgb),
+}
+
+impl Palette {
+    /// Cells never drawn are blank; ids missing from the palette show as a
+    /// magenta `?` so they stand out.
+    pub fn new() -> Self {
+        Palette {
+            tiles: HashMap::new(),
+            background: (' ', [0, 0, 0]),
+            unknown: ('?', [255, 0, 255]),
+        }
+    }
+
+    pub fn with(mut self, id: i32, c: char, colour: Rgb) -> Self {
+        self.tiles.insert(id, (c, colour));
+        self
+    }
+
+    pub fn with_background(mut self, c: char, colour: Rgb) -> Self {
+        self.background = (c, colour);
+        self
+    }
+
+    /// Empty, wall, block, paddle and ball.
+    pub fn arcade() -> Self {
+        Palette::new()
+            .with(0, ' ', [0, 0, 0])
+            .with(1, '#', [128, 128, 128])
+            .with(2, '=', [200, 120, 40])
+            .with(3, '_', [240, 240, 240])
+            .with(4, 'o', [80, 200, 80])
+    }
+
+    /// Black and white hull panels.
+    pub fn hull() -> Self {
+        Palette::new()
+            .with(0, '.', [0, 0, 0])
+            .with(1, '#', [255, 255, 255])
+            .with_background('.', [0, 0, 0])
+    }
+
+    fn lookup(&self, tile: Option<i32>) -> (char, Rgb) {
+        match tile {
+            Some(id) => *self.tiles.get(&id).unwrap_or(&self.unknown),
+            None => self.background,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new()
+    }
+}
+
+/// The smallest rectangle holding every drawn tile, as `(min_x, min_y, width, height)`.
+fn bounds(tiles: &HashMap<(i32, i32), i32>) -> (i32, i32, usize, usize) {
+    if tiles.is_empty() {
+        return (0, 0, 0, 0);
+    }
+
+    let min_x = tiles.keys().map(|&(x, _)| x).min().unwrap();
+    let max_x = tiles.keys().map(|&(x, _)| x).max().unwrap();
+    let min_y = tiles.keys().map(|&(_, y)| y).min().unwrap();
+    let max_y = tiles.keys().map(|&(_, y)| y).max().unwrap();
+
+    // In i64, since the span of two i32s doesn't always fit in one.
+    (
+        min_x,
+        min_y,
+        (max_x as i64 - min_x as i64 + 1) as usize,
+        (max_y as i64 - min_y as i64 + 1) as usize,
+    )
+}
+
+/// The tile `dx` and `dy` from `(x, y)`, which `bounds` keeps in range.
+fn tile_at(display: &TileDisplay, (x, y): (i32, i32), dx: usize, dy: usize) -> Option<i32> {
+    display.tile((x as i64 + dx as i64) as i32, (y as i64 + dy as i64) as i32)
+}
+
+/// Draws the display with y increasing downwards, followed by a score line
+/// if the display has one.
+pub fn to_ascii(display: &TileDisplay, palette: &Palette) -> String {
+    let (min_x, min_y, width, height) = bounds(display.tiles());
+
+    let mut text = String::new();
+    for y in 0..height {
+        for x in 0..width {
+            let (c, _) = palette.lookup(tile_at(display, (min_x, min_y), x, y));
+            text.push(c);
+        }
+        text.push('\n');
+    }
+
+    if let Some(score) = display.score() {
+        text.push_str(&format!("Score: {}\n", score));
+    }
+
+    text
+}
+
+/// Draws the display as a binary (P6) PPM image, each tile `scale` pixels square.
+pub fn to_ppm(display: &TileDisplay, palette: &Palette, scale: usize) -> Vec<u8> {
+    let (min_x, min_y, width, height) = bounds(display.tiles());
+
+    let mut image = format!("P6\n{} {}\n255\n", width * scale, height * scale).into_bytes();
+    for y in 0..height {
+        let row: Vec<u8> = (0..width)
+            .flat_map(|x| {
+                let (_, colour) = palette.lookup(tile_at(display, (min_x, min_y), x, y));
+                std::iter::repeat_n(colour, scale).flatten()
+            })
+            .collect();
+
+        for _ in 0..scale {
+            image.extend_from_slice(&row);
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::device::Device;
+
+    fn draw(display: &mut TileDisplay, triples: &[i32]) {
+        for &value in triples {
+            display.output(value);
+        }
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut display = TileDisplay::new().with_score_cell(-1, 0);
+        draw(
+            &mut display,
+            &[-2, -1, 1, 0, -1, 1, -2, 0, 4, 0, 0, 9, -1, 0, 1234],
+        );
+
+        assert_eq!(
+            to_ascii(&display, &Palette::arcade()),
+            "# #\no ?\nScore: 1234\n"
+        );
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut display = TileDisplay::new();
+        draw(&mut display, &[0, 0, 1, 1, 0, 0]);
+
+        let image = to_ppm(&display, &Palette::hull(), 2);
+        let header = b"P6\n4 2\n255\n";
+        assert_eq!(&image[..header.len()], header);
+
+        let white = [255, 255, 255];
+        let black = [0, 0, 0];
+        let row: Vec<u8> = [white, white, black, black].concat();
+        assert_eq!(&image[header.len()..], &[row.clone(), row].concat()[..]);
+    }
+
+    #[test]
+    fn test_far_apart() {
+        let mut display = TileDisplay::new();
+        draw(&mut display, &[i32::MIN, 0, 1, i32::MAX, 0, 1]);
+        assert_eq!(bounds(display.tiles()), (i32::MIN, 0, 1 << 32, 1));
+
+        let mut display = TileDisplay::new();
+        draw(&mut display, &[0, i32::MAX - 1, 1, 0, i32::MAX, 4]);
+        assert_eq!(to_ascii(&display, &Palette::arcade()), "#\no\n");
+    }
+
+    #[test]
+    fn test_empty() {
+        let display = TileDisplay::new();
+        assert_eq!(to_ascii(&display, &Palette::arcade()), "");
+        assert_eq!(to_ppm(&display, &Palette::arcade(), 4), b"P6\n0 0\n255\n");
+    }
+}
@@ -0,0 +1,326 @@
+use super::{check_patches, Machine, Program, State};
+
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// A search over patched variants of a program, like day 2's noun/verb hunt.
+///
+/// Every combination of patch values is run to completion on a pool of threads,
+/// each resetting one machine per combination.
+/// Combinations are ordered with the first patch most significant, so
+/// `first` returns the same match a nested loop over the patches would find.
+/// Runs that fail, need more input or exceed the step limit never match.
+/// Searching more combinations than fit in a `usize`, or patching outside
+/// the program, is an error.
+#[derive(Debug, Clone)]
+pub struct Search {
+    program: Program,
+    patches: Vec<(usize, Range<i32>)>,
+    input: Vec<i32>,
+    threads: usize,
+    max_steps: Option<usize>,
+}
+
+/// A matching combination of patch values and the machine it left behind.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub values: Vec<i32>,
+    pub machine: Machine,
+}
+
+impl Search {
+    pub fn new<P: Into<Program>>(program: P) -> Self {
+        Search {
+            program: program.into(),
+            patches: Vec::new(),
+            input: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            max_steps: None,
+        }
+    }
+
+    pub fn patch(mut self, addr: usize, values: Range<i32>) -> Self {
+        self.patches.push((addr, values));
+        self
+    }
+
+    pub fn input(mut self, input: &[i32]) -> Self {
+        self.input = input.to_vec();
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// The number of combinations.
+    fn count(&self) -> Result<usize, String> {
+        let widths: Vec<i64> = self.patches.iter().map(|(_, range)| width(range)).collect();
+        if widths.contains(&0) {
+            return Ok(0);
+        }
+        widths
+            .iter()
+            .try_fold(1usize, |count, &width| {
+                usize::try_from(width)
+                    .ok()
+                    .and_then(|width| count.checked_mul(width))
+            })
+            .ok_or_else(|| "too many combinations to search".to_string())
+    }
+
+    /// Only called with an index below `count`, so every width fits.
+    fn values_at(&self, mut index: usize) -> Vec<i32> {
+        let mut values = vec![0; self.patches.len()];
+        for (i, (_, range)) in self.patches.iter().enumerate().rev() {
+            let len = width(range) as usize;
+            values[i] = (range.start as i64 + (index % len) as i64) as i32;
+            index /= len;
+        }
+        values
+    }
+
+    /// Whether `machine` halts when run with these patch values.
+    fn run(&self, machine: &mut Machine, values: &[i32]) -> bool {
+        let patches: Vec<(usize, i32)> = self
+            .patches
+            .iter()
+            .zip(values)
+            .map(|(&(addr, _), &value)| (addr, value))
+            .collect();
+        machine
+            .reset_with(&patches)
+            .expect("scan checks the patch addresses");
+        for &value in &self.input {
+            machine.push_input(value);
+        }
+
+        loop {
+            if self.max_steps.is_some_and(|max| machine.steps() >= max) {
+                return false;
+            }
+            match machine.step() {
+                Ok(State::Running) => {}
+                Ok(State::Halted) => return true,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Runs combinations on every thread, skipping any at or past `limit`,
+    /// which `visit` may lower to cancel outstanding work.
+    fn scan<F>(&self, limit: &AtomicUsize, visit: F) -> Result<(), String>
+    where
+        F: Fn(usize, Found) + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let count = self.count()?;
+        check_patches(
+            self.patches.iter().map(|&(addr, _)| addr),
+            self.program.len(),
+        )?;
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| {
+                    let mut machine = Machine::new(&self.program);
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= count || index >= limit.load(Ordering::Relaxed) {
+                            break;
+                        }
+
+                        let values = self.values_at(index);
+                        if self.run(&mut machine, &values) {
+                            let machine = machine.clone();
+                            visit(index, Found { values, machine });
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    /// The match a serial search would find first.
+    pub fn first<P>(&self, predicate: P) -> Result<Option<Found>, String>
+    where
+        P: Fn(&Machine) -> bool + Sync,
+    {
+        let limit = AtomicUsize::new(usize::MAX);
+        let found = Mutex::new(None::<(usize, Found)>);
+
+        self.scan(&limit, |index, candidate| {
+            if predicate(&candidate.machine) {
+                limit.fetch_min(index, Ordering::Relaxed);
+
+                let mut found = found.lock().unwrap();
+                if found.as_ref().is_none_or(|(first, _)| index < *first) {
+                    *found = Some((index, candidate));
+                }
+            }
+        })?;
+
+        Ok(found.into_inner().unwrap().map(|(_, found)| found))
+    }
+
+    /// Every match, in serial search order.
+    pub fn all<P>(&self, predicate: P) -> Result<Vec<Found>, String>
+    where
+        P: Fn(&Machine) -> bool + Sync,
+    {
+        let limit = AtomicUsize::new(usize::MAX);
+        let found = Mutex::new(Vec::new());
+
+        self.scan(&limit, |index, candidate| {
+            if predicate(&candidate.machine) {
+                found.lock().unwrap().push((index, candidate));
+            }
+        })?;
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_by_key(|&(index, _)| index);
+        Ok(found.into_iter().map(|(_, candidate)| candidate).collect())
+    }
+
+    /// The run with the highest score, ignoring runs scored `None`. Ties go to
+    /// the earlier combination.
+    pub fn best<S>(&self, score: S) -> Result<Option<Found>, String>
+    where
+        S: Fn(&Machine) -> Option<i64> + Sync,
+    {
+        let limit = AtomicUsize::new(usize::MAX);
+        let best = Mutex::new(None::<(i64, usize, Found)>);
+
+        self.scan(&limit, |index, candidate| {
+            if let Some(score) = score(&candidate.machine) {
+                let mut best = best.lock().unwrap();
+                let better = match &*best {
+                    None => true,
+                    Some((best_score, best_index, _)) => {
+                        score > *best_score || (score == *best_score && index < *best_index)
+                    }
+                };
+                if better {
+                    *best = Some((score, index, candidate));
+                }
+            }
+        })?;
+
+        Ok(best.into_inner().unwrap().map(|(_, _, found)| found))
+    }
+}
+
+fn width(range: &Range<i32>) -> i64 {
+    (range.end as i64 - range.start as i64).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stores 10 * [9] + [10] in [0].
+    const PROGRAM: [i32; 12] = [1002, 9, 10, 11, 1, 11, 10, 0, 99, 0, 0, 0];
+
+    #[test]
+    fn test_first() {
+        let search = Search::new(&PROGRAM).patch(9, 0..10).patch(10, 0..10);
+
+        let found = search.first(|m| m.memory()[0] == 42).unwrap().unwrap();
+        assert_eq!(found.values, [4, 2]);
+        assert_eq!(found.machine.memory()[0], 42);
+
+        let found = search
+            .threads(1)
+            .first(|m| m.memory()[0] % 7 == 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.values, [0, 3]);
+
+        assert!(Search::new(&PROGRAM)
+            .patch(9, 0..10)
+            .first(|m| m.memory()[0] < 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_all() {
+        let found = Search::new(&PROGRAM)
+            .patch(9, 0..10)
+            .patch(10, 0..10)
+            .threads(4)
+            .all(|m| m.memory()[0] % 11 == 0)
+            .unwrap();
+
+        let values: Vec<Vec<i32>> = found.into_iter().map(|f| f.values).collect();
+        assert_eq!(values.len(), 10);
+        assert_eq!(values[0], [0, 0]);
+        assert_eq!(values[9], [9, 9]);
+    }
+
+    #[test]
+    fn test_best() {
+        let found = Search::new(&PROGRAM)
+            .patch(9, -5..5)
+            .patch(10, -5..5)
+            .best(|m| Some(-(m.memory()[0] as i64 - 17).abs()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.values, [2, -3]);
+    }
+
+    #[test]
+    fn test_failed_runs_never_match() {
+        let looping = [1105, 1, 0, 3, 0, 99];
+        assert!(Search::new(&looping)
+            .patch(1, 0..2)
+            .max_steps(100)
+            .all(|_| true)
+            .unwrap()
+            .is_empty());
+
+        let found = Search::new(&[3, 0, 99]).input(&[5]).all(|_| true).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].machine.memory()[0], 5);
+    }
+
+    #[test]
+    fn test_wide_ranges() {
+        let search = Search::new(&PROGRAM).patch(9, -2_000_000_000..2_000_000_000);
+        assert_eq!(search.count(), Ok(4_000_000_000));
+        assert_eq!(search.values_at(0), [-2_000_000_000]);
+        assert_eq!(search.values_at(3_999_999_999), [1_999_999_999]);
+
+        let found = Search::new(&PROGRAM)
+            .patch(9, i32::MIN..i32::MAX)
+            .patch(10, i32::MIN..i32::MAX)
+            .patch(11, i32::MIN..i32::MAX)
+            .first(|_| true);
+        assert!(found.is_err());
+
+        let empty = Search::new(&PROGRAM)
+            .patch(9, i32::MIN..i32::MAX)
+            .patch(10, i32::MIN..i32::MAX)
+            .patch(11, i32::MIN..i32::MAX)
+            .patch(0, 5..5);
+        assert_eq!(empty.count(), Ok(0));
+
+        assert_eq!(
+            Search::new(&PROGRAM)
+                .patch(12, 0..2)
+                .all(|_| true)
+                .unwrap_err(),
+            "patch address 12 is outside the program (12 words)"
+        );
+    }
+}
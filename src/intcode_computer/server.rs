@@ -0,0 +1,492 @@
+//! A line-based debug protocol for driving a `Debugger` over a socket, so
+//! editors and scripts can debug a machine from outside the process.
+//!
+//! Each request is one line, and each gets one line back: `ok`, followed by
+//! any results separated by spaces, or `error` and a message. Commands:
+//!
+//! ```text
+//! step [n]                  run n instructions (default 1)
+//! continue [n]              run until a breakpoint, watchpoint or the machine
+//!                           stops, or for at most n instructions
+//! break addr [if cond]      add a breakpoint; `break if cond` checks every instruction
+//! watch addr [if cond]      add a watchpoint
+//! delete                    remove every breakpoint and watchpoint
+//! read addr [count]         memory values, one word unless a count is given
+//! write addr value...       store consecutive values from addr
+//! registers                 ip, relative base and steps taken
+//! input value...            queue input values
+//! output                    take the outputs produced so far
+//! quit                      stop serving
+//! ```
+//!
+//! Running commands reply with why they stopped: `running ip 6` after steps
+//! with nothing to report, `halted ip 22`, `awaiting-input ip 0`,
+//! `breakpoint 0 ip 9`, `watchpoint 0 ip 13 old 4 new 7`, or `limit ip 6`
+//! when `continue` ran out of steps. Neither command runs more than 10000000
+//! instructions at once, which is also the default for `continue`; a `step`
+//! cut short that way replies `limit` too. A machine error is an `error`
+//! reply, and leaves the machine at the failing instruction. Request lines
+//! longer than 64KiB get an `error` reply without being run.
+
+use super::debugger::{Debugger, Stop};
+use super::State;
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::panic::{self, AssertUnwindSafe};
+
+/// The most instructions one `step` or `continue` runs, and how many
+/// `continue` runs when not told. Tests that reach it use a smaller one.
+#[cfg(not(test))]
+const RUN_LIMIT: i64 = 10_000_000;
+#[cfg(test)]
+const RUN_LIMIT: i64 = 10_000;
+
+/// The longest request line that's run.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Runs one request line against `debugger`, returning the reply without its
+/// `ok` or `error` prefix.
+pub fn command(debugger: &mut Debugger, line: &str) -> Result<String, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+    let number = |i: usize, what: &str| -> Result<i64, String> {
+        let arg = args.get(i).ok_or(format!("{} needs {}", name, what))?;
+        arg.parse().map_err(|_| format!("{} isn't a number", arg))
+    };
+    let addr = |i: usize| -> Result<usize, String> {
+        let addr = number(i, "an address")?;
+        let len = debugger.machine.memory.len();
+        usize::try_from(addr)
+            .ok()
+            .filter(|&addr| addr < len)
+            .ok_or(format!(
+                "address {} is outside memory ({} words)",
+                addr, len
+            ))
+    };
+
+    match name {
+        "step" | "s" => {
+            let count = match args.first() {
+                Some(_) => number(0, "a count")?,
+                None => 1,
+            };
+            for _ in 0..count.min(RUN_LIMIT) {
+                if let Some(stop) = debugger.step().map_err(|e| e.to_string())? {
+                    return Ok(describe(debugger, stop));
+                }
+            }
+            if count > RUN_LIMIT {
+                return Ok(describe(debugger, Stop::StepLimit));
+            }
+            Ok(format!("running ip {}", debugger.machine.instr_ptr))
+        }
+        "continue" | "c" => {
+            let limit = match args.first() {
+                Some(_) => number(0, "a count")?,
+                None => RUN_LIMIT,
+            };
+            let stop = debugger
+                .cont_for(limit.clamp(0, RUN_LIMIT) as usize)
+                .map_err(|e| e.to_string())?;
+            Ok(describe(debugger, stop))
+        }
+        "break" | "b" | "watch" | "w" => {
+            let (at, condition) = match args.iter().position(|&arg| arg == "if") {
+                Some(i) => (&args[..i], Some(args[i + 1..].join(" "))),
+                None => (&args[..], None),
+            };
+            let addr = match at.len() {
+                0 => None,
+                1 => Some(addr(0)?),
+                _ => return Err(format!("unexpected {}", at[1])),
+            };
+            let condition = condition.as_deref();
+            if name.starts_with('b') {
+                let index = debugger.add_breakpoint(addr, condition)?;
+                Ok(format!("breakpoint {}", index))
+            } else {
+                let addr = addr.ok_or("watch needs an address")?;
+                let index = debugger.add_watchpoint(addr, condition)?;
+                Ok(format!("watchpoint {}", index))
+            }
+        }
+        "delete" => {
+            debugger.clear();
+            Ok(String::new())
+        }
+        "read" | "x" => {
+            let start = addr(0)?;
+            let count = match args.get(1) {
+                Some(_) => number(1, "a count")?.max(0) as usize,
+                None => 1,
+            };
+            let memory = &debugger.machine.memory;
+            let end = memory.len().min(start.saturating_add(count));
+            let values: Vec<String> = (start..end).map(|a| memory[a].to_string()).collect();
+            Ok(values.join(" "))
+        }
+        "write" => {
+            let start = addr(0)?;
+            let values = (1..args.len().max(2))
+                .map(|i| value(number(i, "a value")?))
+                .collect::<Result<Vec<i32>, String>>()?;
+            let len = debugger.machine.memory.len();
+            if start + values.len() > len {
+                return Err(format!("write runs past the end of memory ({} words)", len));
+            }
+            for (i, value) in values.into_iter().enumerate() {
+                debugger.machine.memory[start + i] = value;
+            }
+            Ok(String::new())
+        }
+        "registers" | "regs" => {
+            let machine = &debugger.machine;
+            Ok(format!(
+                "ip {} rb {} steps {}",
+                machine.instr_ptr, machine.relative_base, machine.steps
+            ))
+        }
+        "input" => {
+            let values = (0..args.len().max(1))
+                .map(|i| value(number(i, "a value")?))
+                .collect::<Result<Vec<i32>, String>>()?;
+            for value in values {
+                debugger.machine.push_input(value);
+            }
+            Ok(String::new())
+        }
+        "output" => {
+            let values: Vec<String> = debugger
+                .machine
+                .take_output()
+                .iter()
+                .map(|v| v.to_string())
+                .collect();
+            Ok(values.join(" "))
+        }
+        "" => Err("empty command".to_string()),
+        _ => Err(format!("unknown command {}", name)),
+    }
+}
+
+fn value(n: i64) -> Result<i32, String> {
+    i32::try_from(n).map_err(|_| format!("{} doesn't fit in a word", n))
+}
+
+fn describe(debugger: &Debugger, stop: Stop) -> String {
+    let ip = debugger.machine.instr_ptr;
+    match stop {
+        Stop::Breakpoint(index) => format!("breakpoint {} ip {}", index, ip),
+        Stop::Watchpoint { index, old, new } => {
+            format!("watchpoint {} ip {} old {} new {}", index, ip, old, new)
+        }
+        Stop::Machine(State::Halted) => format!("halted ip {}", ip),
+        Stop::Machine(State::AwaitingInput) => format!("awaiting-input ip {}", ip),
+        Stop::Machine(State::Running) => format!("running ip {}", ip),
+        Stop::StepLimit => format!("limit ip {}", ip),
+    }
+}
+
+/// Answers requests from `reader` on `writer` until the client sends `quit`,
+/// returning true, or disconnects, returning false.
+pub fn serve<R: BufRead, W: Write>(
+    debugger: &mut Debugger,
+    mut reader: R,
+    mut writer: W,
+) -> io::Result<bool> {
+    loop {
+        let mut line = Vec::new();
+        let limit = MAX_LINE as u64 + 1;
+        if reader.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+            return Ok(false);
+        }
+        let reply = if line.last() != Some(&b'\n') && line.len() > MAX_LINE {
+            reader.skip_until(b'\n')?;
+            Err(format!("request longer than {} bytes", MAX_LINE))
+        } else {
+            match String::from_utf8(line) {
+                Ok(line) if line.trim() == "quit" => {
+                    writeln!(writer, "ok")?;
+                    writer.flush()?;
+                    return Ok(true);
+                }
+                // A panic in a command becomes an error reply. Anything that
+                // could overflow the stack instead, like a deeply nested
+                // condition, is refused by the limits on lines and expressions.
+                Ok(line) => panic::catch_unwind(AssertUnwindSafe(|| command(debugger, &line)))
+                    .unwrap_or_else(|_| Err(format!("internal error running {}", line.trim()))),
+                Err(_) => Err("request isn't UTF-8".to_string()),
+            }
+        };
+        match reply {
+            Ok(reply) if reply.is_empty() => writeln!(writer, "ok")?,
+            Ok(reply) => writeln!(writer, "ok {}", reply)?,
+            Err(e) => writeln!(writer, "error {}", e)?,
+        }
+        writer.flush()?;
+    }
+}
+
+/// A bound socket that serves one client at a time.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    /// Binds `unix:path` to a Unix domain socket, or `host:port` to a TCP
+    /// port, which must be on the loopback interface.
+    pub fn bind(addr: &str) -> Result<Listener, String> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            return bind_unix(path);
+        }
+
+        let addrs: Vec<_> = addr
+            .to_socket_addrs()
+            .map_err(|e| format!("bad address {}: {}", addr, e))?
+            .collect();
+        if addrs.is_empty() || addrs.iter().any(|a| !a.ip().is_loopback()) {
+            return Err(format!("{} isn't a localhost address", addr));
+        }
+        TcpListener::bind(&addrs[..])
+            .map(Listener::Tcp)
+            .map_err(|e| format!("can't listen on {}: {}", addr, e))
+    }
+
+    /// Where clients should connect, in the form `bind` takes.
+    pub fn local_addr(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map_or_else(|e| e.to_string(), |a| a.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => format!("unix:{}", path),
+        }
+    }
+
+    /// Serves clients one after another until one sends `quit`.
+    pub fn run(&self, debugger: &mut Debugger) -> Result<(), String> {
+        loop {
+            let quit = match self {
+                Listener::Tcp(listener) => {
+                    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+                    let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+                    serve(debugger, reader, stream)
+                }
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => {
+                    let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+                    let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+                    serve(debugger, reader, stream)
+                }
+            };
+            // A client that drops its connection mid-reply doesn't stop the
+            // server.
+            if quit.unwrap_or(false) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<Listener, String> {
+    UnixListener::bind(path)
+        .map(|listener| Listener::Unix(listener, path.to_string()))
+        .map_err(|e| format!("can't listen on {}: {}", path, e))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &str) -> Result<Listener, String> {
+    Err(format!("can't listen on {}: no Unix sockets here", path))
+}
+
+/// Removes the socket file, so the path can be bound again.
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::tests::summer;
+    use crate::intcode_computer::Machine;
+
+    use std::io::Cursor;
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn debugger() -> Debugger {
+        Debugger::new(Machine::new(summer()))
+    }
+
+    fn session(debugger: &mut Debugger, requests: &str) -> Vec<String> {
+        let mut replies = Vec::new();
+        serve(debugger, Cursor::new(requests), &mut replies).unwrap();
+        String::from_utf8(replies)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut debugger = debugger();
+        let replies = session(
+            &mut debugger,
+            "continue\ninput 4\nstep\nregisters\nbreak 9 if mem[100] < 3\nc\nread 100 2\n\
+             write 101 1000\nwatch 101\ncontinue\ndelete\nc\noutput\nread 102\nbogus\n",
+        );
+        assert_eq!(
+            replies,
+            [
+                "ok awaiting-input ip 0",
+                "ok",
+                "ok running ip 2",
+                "ok ip 2 rb 0 steps 1",
+                "ok breakpoint 0",
+                "ok breakpoint 0 ip 9",
+                "ok 2 7",
+                "ok",
+                "ok watchpoint 0",
+                "ok watchpoint 0 ip 13 old 1000 new 1002",
+                "ok",
+                "ok halted ip 22",
+                "ok 1003",
+                "error address 102 is outside memory (102 words)",
+                "error unknown command bogus",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        let mut debugger = debugger();
+        let replies = session(
+            &mut debugger,
+            "write 101\nwrite 101 1 2\ninput 9999999999\nbreak 1 2\nwatch if x\nstep x\n",
+        );
+        assert_eq!(
+            replies,
+            [
+                "error write needs a value",
+                "error write runs past the end of memory (102 words)",
+                "error 9999999999 doesn't fit in a word",
+                "error unexpected 2",
+                "error watch needs an address",
+                "error x isn't a number",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_machine_errors_and_endless_loops() {
+        let mut debugger = debugger();
+        let replies = session(
+            &mut debugger,
+            "input 1\nwrite 1 5000\nstep\nregisters\nwrite 0 1105 1 0\ncontinue 100\nregisters\n",
+        );
+        assert_eq!(
+            replies,
+            [
+                "ok",
+                "ok",
+                "error address 5000 is outside memory at ip 0",
+                "ok ip 0 rb 0 steps 0",
+                "ok",
+                "ok limit ip 0",
+                "ok ip 0 rb 0 steps 100",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let mut debugger = debugger();
+        let requests = format!(
+            "write 0 1105 1 0\nstep 1000000000000\nbreak 0 if {}\nbreak 0 if {}1\n{}\nregisters\n",
+            "(".repeat(100_000),
+            "(".repeat(1_000),
+            "x".repeat(MAX_LINE + 1),
+        );
+        assert_eq!(
+            session(&mut debugger, &requests),
+            [
+                "ok".to_string(),
+                "ok limit ip 0".to_string(),
+                "error request longer than 65536 bytes".to_string(),
+                "error expression nested too deeply".to_string(),
+                "error request longer than 65536 bytes".to_string(),
+                format!("ok ip 0 rb 0 steps {}", RUN_LIMIT),
+            ]
+        );
+
+        let mut replies = Vec::new();
+        serve(&mut debugger, Cursor::new(b"\xff\nquit\n"), &mut replies).unwrap();
+        assert_eq!(replies, b"error request isn't UTF-8\nok\n");
+    }
+
+    #[test]
+    fn test_tcp_client() {
+        assert!(Listener::bind("192.0.2.1:0").is_err());
+        let listener = Listener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr();
+        let server = thread::spawn(move || {
+            let mut debugger = debugger();
+            listener.run(&mut debugger).unwrap();
+            debugger
+        });
+
+        // A client that goes away doesn't stop the server.
+        drop(TcpStream::connect(&addr).unwrap());
+
+        let mut client = TcpStream::connect(&addr).unwrap();
+        let mut replies = BufReader::new(client.try_clone().unwrap()).lines();
+        let mut request = |line: &str| {
+            writeln!(client, "{}", line).unwrap();
+            replies.next().unwrap().unwrap()
+        };
+        assert_eq!(request("input 3"), "ok");
+        assert_eq!(request("continue"), "ok halted ip 22");
+        assert_eq!(request("output"), "ok 6");
+        assert_eq!(request("quit"), "ok");
+
+        let debugger = server.join().unwrap();
+        assert_eq!(debugger.machine.instr_ptr(), 22);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_client() {
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("intcode-{}.sock", std::process::id()));
+        let addr = format!("unix:{}", path.display());
+        let listener = Listener::bind(&addr).unwrap();
+        assert_eq!(listener.local_addr(), addr);
+        let server = thread::spawn(move || listener.run(&mut debugger()));
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        let mut replies = BufReader::new(client.try_clone().unwrap()).lines();
+        writeln!(client, "regs\nquit").unwrap();
+        assert_eq!(replies.next().unwrap().unwrap(), "ok ip 0 rb 0 steps 0");
+        assert_eq!(replies.next().unwrap().unwrap(), "ok");
+
+        assert_eq!(server.join().unwrap(), Ok(()));
+        assert!(!path.exists());
+    }
+}
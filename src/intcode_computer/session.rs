@@ -0,0 +1,331 @@
+use super::{Error, Machine, State};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    Input(i32),
+    Output(i32),
+}
+
+/// An I/O event and the step count at which it happened.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Entry {
+    pub step: usize,
+    pub event: Event,
+}
+
+/// Every input consumed and output produced during a run, in order.
+///
+/// Sessions are saved as text, one `<step> in <value>` or `<step> out <value>`
+/// line per event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub entries: Vec<Entry>,
+}
+
+impl Session {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Session> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = i32> + '_ {
+        self.entries.iter().filter_map(|entry| match entry.event {
+            Event::Input(value) => Some(value),
+            Event::Output(_) => None,
+        })
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            match entry.event {
+                Event::Input(value) => writeln!(f, "{} in {}", entry.step, value)?,
+                Event::Output(value) => writeln!(f, "{} out {}", entry.step, value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Session {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let bad_line = || format!("bad session line {}: {}", n + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(bad_line());
+            }
+
+            let step = fields[0].parse().map_err(|_| bad_line())?;
+            let value = fields[2].parse().map_err(|_| bad_line())?;
+            let event = match fields[1] {
+                "in" => Event::Input(value),
+                "out" => Event::Output(value),
+                _ => return Err(bad_line()),
+            };
+            entries.push(Entry { step, event });
+        }
+
+        Ok(Session { entries })
+    }
+}
+
+/// Steps a machine while logging its I/O, for callers that drive the machine
+/// themselves.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    session: Session,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    /// Same as `machine.step()`, logging any input consumed or output produced.
+    pub fn step(&mut self, machine: &mut Machine) -> Result<State, Error> {
+        let step = machine.steps;
+        let next_input = machine.input.front().copied();
+        let queued = machine.input.len();
+        let produced = machine.output.len();
+
+        let state = machine.step()?;
+
+        if machine.input.len() < queued {
+            self.session.entries.push(Entry {
+                step,
+                event: Event::Input(next_input.unwrap()),
+            });
+        }
+        for &value in &machine.output[produced..] {
+            self.session.entries.push(Entry {
+                step,
+                event: Event::Output(value),
+            });
+        }
+
+        Ok(state)
+    }
+
+    /// Runs until the machine halts or needs input that isn't queued.
+    pub fn run(&mut self, machine: &mut Machine) -> Result<State, Error> {
+        loop {
+            match self.step(machine)? {
+                State::Running => {}
+                state => return Ok(state),
+            }
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn finish(self) -> Session {
+        self.session
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    Machine(Error),
+    /// The `index`th event of the replay didn't match the recording. `None`
+    /// means that side had no event at that point.
+    Diverged {
+        index: usize,
+        expected: Option<Entry>,
+        actual: Option<Entry>,
+    },
+    /// The machine asked for more input than was recorded.
+    OutOfInput {
+        step: usize,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |entry: &Option<Entry>| match entry {
+            Some(Entry {
+                step,
+                event: Event::Input(value),
+            }) => format!("input {} at step {}", value, step),
+            Some(Entry {
+                step,
+                event: Event::Output(value),
+            }) => format!("output {} at step {}", value, step),
+            None => "nothing".to_string(),
+        };
+
+        match self {
+            ReplayError::Machine(e) => write!(f, "machine error during replay: {}", e),
+            ReplayError::Diverged {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "replay diverged at event {}: expected {}, got {}",
+                index,
+                describe(expected),
+                describe(actual)
+            ),
+            ReplayError::OutOfInput { step } => {
+                write!(
+                    f,
+                    "machine wanted more input than recorded at step {}",
+                    step
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Runs `machine` feeding it the recorded inputs, and checks that every event
+/// happens with the same value at the same step as in `session`. Stops at the
+/// first divergence.
+pub fn replay(machine: &mut Machine, session: &Session) -> Result<(), ReplayError> {
+    let mut recorder = Recorder::new();
+    let mut inputs = session.inputs();
+    let mut checked = 0;
+
+    loop {
+        let state = recorder.step(machine).map_err(ReplayError::Machine)?;
+
+        let actual = &recorder.session.entries;
+        while checked < actual.len() {
+            let expected = session.entries.get(checked);
+            if expected != Some(&actual[checked]) {
+                return Err(ReplayError::Diverged {
+                    index: checked,
+                    expected: expected.copied(),
+                    actual: Some(actual[checked]),
+                });
+            }
+            checked += 1;
+        }
+
+        match state {
+            State::Running => {}
+            State::AwaitingInput => match inputs.next() {
+                Some(value) => machine.push_input(value),
+                None => {
+                    return Err(ReplayError::OutOfInput {
+                        step: machine.steps,
+                    })
+                }
+            },
+            State::Halted => break,
+        }
+    }
+
+    match session.entries.get(checked) {
+        None => Ok(()),
+        Some(&expected) => Err(ReplayError::Diverged {
+            index: checked,
+            expected: Some(expected),
+            actual: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Echoes its input doubled until it reads a zero.
+    fn doubler() -> Machine {
+        Machine::new(vec![
+            3, 15, 1006, 15, 14, 1002, 15, 2, 15, 4, 15, 1105, 1, 0, 99, 0,
+        ])
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut machine = doubler();
+        machine.push_input(3);
+        machine.push_input(-4);
+        machine.push_input(0);
+
+        let mut recorder = Recorder::new();
+        assert_eq!(recorder.run(&mut machine), Ok(State::Halted));
+        let session = recorder.finish();
+
+        assert_eq!(
+            session.to_string(),
+            "0 in 3\n3 out 6\n5 in -4\n8 out -8\n10 in 0\n"
+        );
+        assert_eq!(session.to_string().parse(), Ok(session.clone()));
+
+        assert_eq!(replay(&mut doubler(), &session), Ok(()));
+    }
+
+    #[test]
+    fn test_replay_divergence() {
+        let session: Session = "0 in 3\n3 out 7\n".parse().unwrap();
+
+        assert_eq!(
+            replay(&mut doubler(), &session),
+            Err(ReplayError::Diverged {
+                index: 1,
+                expected: Some(Entry {
+                    step: 3,
+                    event: Event::Output(7)
+                }),
+                actual: Some(Entry {
+                    step: 3,
+                    event: Event::Output(6)
+                }),
+            })
+        );
+
+        let session: Session = "0 in 0\n1 out 0\n".parse().unwrap();
+        assert_eq!(
+            replay(&mut doubler(), &session),
+            Err(ReplayError::Diverged {
+                index: 1,
+                expected: Some(Entry {
+                    step: 1,
+                    event: Event::Output(0)
+                }),
+                actual: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_replay_out_of_input() {
+        let session: Session = "0 in 1\n3 out 2\n".parse().unwrap();
+        assert_eq!(
+            replay(&mut doubler(), &session),
+            Err(ReplayError::OutOfInput { step: 5 })
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!("0 in".parse::<Session>().is_err());
+        assert!("0 sideways 3".parse::<Session>().is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use super::{Instruction, OpCode, ParameterMode};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+
+/// `constant + coeffs[0] * x0 + coeffs[1] * x1 + ...` over the symbolic cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Affine {
+    pub constant: i64,
+    pub coeffs: Vec<i64>,
+}
+
+impl Affine {
+    fn constant(value: i64, symbols: usize) -> Self {
+        Affine {
+            constant: value,
+            coeffs: vec![0; symbols],
+        }
+    }
+
+    fn symbol(index: usize, symbols: usize) -> Self {
+        let mut coeffs = vec![0; symbols];
+        coeffs[index] = 1;
+        Affine {
+            constant: 0,
+            coeffs,
+        }
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        if self.coeffs.iter().all(|&c| c == 0) {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    /// The value at `values`, or `None` if it doesn't fit in an `i64`.
+    pub fn eval(&self, values: &[i64]) -> Option<i64> {
+        self.coeffs
+            .iter()
+            .zip(values)
+            .try_fold(self.constant, |sum, (c, v)| {
+                sum.checked_add(c.checked_mul(*v)?)
+            })
+    }
+
+    fn add(&self, other: &Affine) -> Option<Affine> {
+        Some(Affine {
+            constant: self.constant.checked_add(other.constant)?,
+            coeffs: self
+                .coeffs
+                .iter()
+                .zip(&other.coeffs)
+                .map(|(a, b)| a.checked_add(*b))
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    fn scale(&self, k: i64) -> Option<Affine> {
+        Some(Affine {
+            constant: self.constant.checked_mul(k)?,
+            coeffs: self
+                .coeffs
+                .iter()
+                .map(|c| c.checked_mul(k))
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+impl fmt::Display for Affine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.constant)?;
+        for (i, &c) in self.coeffs.iter().enumerate() {
+            match c {
+                0 => {}
+                1 => write!(f, " + x{}", i)?,
+                c if c < 0 => write!(f, " - {}*x{}", -c, i)?,
+                c => write!(f, " + {}*x{}", c, i)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a program couldn't be analysed as a linear function of its symbols.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Unsupported {
+    SymbolicOpCode {
+        ip: usize,
+    },
+    SymbolicAddress {
+        ip: usize,
+    },
+    SymbolicBranch {
+        ip: usize,
+    },
+    NonLinear {
+        ip: usize,
+    },
+    Overflow {
+        ip: usize,
+    },
+    InvalidInstruction {
+        ip: usize,
+    },
+    OutOfBounds {
+        ip: usize,
+    },
+    OutOfInput {
+        ip: usize,
+    },
+    StepLimit,
+    /// A symbol was asked for at an address outside the program.
+    SymbolOutOfBounds {
+        addr: usize,
+    },
+}
+
+/// Final memory and outputs, each as a function of the symbolic cells. Cells
+/// derived from a read through a symbolic address hold the reason instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub memory: Vec<Result<Affine, Unsupported>>,
+    pub output: Vec<Affine>,
+}
+
+/// Runs `program` with the cells at `symbols` replaced by unknowns x0, x1, ...
+/// tracking every value as an affine function of them.
+///
+/// Only Add and multiplication by a constant keep values linear. Anything
+/// that needs a symbolic value to be concrete (an opcode, an address, a jump
+/// or comparison) or a product of two symbolic values gives up with the reason.
+/// A read through a symbolic address only fails once its value is used this
+/// way or output, so results that are overwritten unused don't matter.
+/// Arithmetic is done in `i64`.
+pub fn analyze(
+    program: &[i32],
+    symbols: &[usize],
+    input: &[i32],
+    max_steps: usize,
+) -> Result<Analysis, Unsupported> {
+    let n = symbols.len();
+    let mut memory: Vec<Result<Affine, Unsupported>> = program
+        .iter()
+        .map(|&v| Ok(Affine::constant(v as i64, n)))
+        .collect();
+    for (i, &addr) in symbols.iter().enumerate() {
+        *memory
+            .get_mut(addr)
+            .ok_or(Unsupported::SymbolOutOfBounds { addr })? = Ok(Affine::symbol(i, n));
+    }
+    let mut input: VecDeque<i32> = input.iter().copied().collect();
+    let mut output = Vec::new();
+
+    let mut ip = 0;
+    let mut base: i64 = 0;
+    for _ in 0..max_steps {
+        let concrete = |addr: usize, memory: &[Result<Affine, Unsupported>], err: Unsupported| {
+            memory
+                .get(addr)
+                .ok_or(Unsupported::OutOfBounds { ip })?
+                .clone()?
+                .as_constant()
+                .ok_or(err)
+        };
+        let address = |value: i64| {
+            if value >= 0 && (value as usize) < memory.len() {
+                Ok(value as usize)
+            } else {
+                Err(Unsupported::OutOfBounds { ip })
+            }
+        };
+
+        let word = concrete(ip, &memory, Unsupported::SymbolicOpCode { ip })?;
+        let instr = Instruction::decode(word as i32, false)
+            .map_err(|_| Unsupported::InvalidInstruction { ip })?;
+        if ip + OpCode::value_count(instr.op) > memory.len() {
+            return Err(Unsupported::OutOfBounds { ip });
+        }
+
+        let mut operands = Vec::new();
+        for (i, &mode) in instr.modes.iter().enumerate() {
+            let offset = match mode {
+                ParameterMode::Relative => base,
+                _ => 0,
+            };
+            operands.push(match mode {
+                _ if OpCode::write_param(instr.op) == Some(i) => Ok(Affine::constant(
+                    concrete(ip + 1 + i, &memory, Unsupported::SymbolicAddress { ip })? + offset,
+                    n,
+                )),
+                ParameterMode::Immediate => memory[ip + 1 + i].clone(),
+                ParameterMode::Position | ParameterMode::Relative => {
+                    match concrete(ip + 1 + i, &memory, Unsupported::SymbolicAddress { ip }) {
+                        Ok(addr) => memory[address(addr + offset)?].clone(),
+                        Err(err @ Unsupported::SymbolicAddress { .. }) => Err(err),
+                        Err(err) => return Err(err),
+                    }
+                }
+            });
+        }
+        let write_addr = match OpCode::write_param(instr.op) {
+            Some(i) => address(operands[i].clone()?.constant)?,
+            None => 0,
+        };
+        let next = ip + OpCode::value_count(instr.op);
+
+        match instr.op {
+            OpCode::Add => {
+                memory[write_addr] = match (&operands[0], &operands[1]) {
+                    (Ok(a), Ok(b)) => Ok(a.add(b).ok_or(Unsupported::Overflow { ip })?),
+                    (Err(err), _) | (_, Err(err)) => Err(*err),
+                };
+            }
+            OpCode::Mul => {
+                memory[write_addr] = match (&operands[0], &operands[1]) {
+                    (Ok(a), Ok(b)) => {
+                        let product = match (a.as_constant(), b.as_constant()) {
+                            (Some(k), _) => b.scale(k),
+                            (_, Some(k)) => a.scale(k),
+                            _ => return Err(Unsupported::NonLinear { ip }),
+                        };
+                        Ok(product.ok_or(Unsupported::Overflow { ip })?)
+                    }
+                    (Err(err), _) | (_, Err(err)) => Err(*err),
+                };
+            }
+            OpCode::Input => {
+                let value = input.pop_front().ok_or(Unsupported::OutOfInput { ip })?;
+                memory[write_addr] = Ok(Affine::constant(value as i64, n));
+            }
+            OpCode::Output => output.push(operands[0].clone()?),
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let condition = operands[0]
+                    .clone()?
+                    .as_constant()
+                    .ok_or(Unsupported::SymbolicBranch { ip })?;
+                let target = operands[1]
+                    .clone()?
+                    .as_constant()
+                    .ok_or(Unsupported::SymbolicBranch { ip })?;
+                let jump = match instr.op {
+                    OpCode::JumpIfTrue => condition != 0,
+                    _ => condition == 0,
+                };
+                if jump {
+                    ip = address(target)?;
+                    continue;
+                }
+            }
+            OpCode::LessThan | OpCode::Equals => {
+                let a = operands[0]
+                    .clone()?
+                    .as_constant()
+                    .ok_or(Unsupported::SymbolicBranch { ip })?;
+                let b = operands[1]
+                    .clone()?
+                    .as_constant()
+                    .ok_or(Unsupported::SymbolicBranch { ip })?;
+                let result = match instr.op {
+                    OpCode::LessThan => a < b,
+                    _ => a == b,
+                };
+                memory[write_addr] = Ok(Affine::constant(result as i64, n));
+            }
+            OpCode::AdjustBase => {
+                base += operands[0]
+                    .clone()?
+                    .as_constant()
+                    .ok_or(Unsupported::SymbolicAddress { ip })?;
+            }
+            OpCode::Halt => return Ok(Analysis { memory, output }),
+        }
+
+        ip = next;
+    }
+
+    Err(Unsupported::StepLimit)
+}
+
+/// Finds symbol values within `ranges` for which `expr` equals `target`,
+/// preferring the first in nested-loop order over the ranges.
+pub fn solve(expr: &Affine, target: i64, ranges: &[Range<i64>]) -> Option<Vec<i64>> {
+    if ranges.iter().any(|r| r.is_empty()) {
+        return None;
+    }
+
+    // Symbols after the last one that matters stay at the start of their range.
+    let mut values: Vec<i64> = ranges.iter().map(|r| r.start).collect();
+    let solved = match expr.coeffs.iter().rposition(|&c| c != 0) {
+        Some(solved) => solved,
+        None if expr.constant == target => return Some(values),
+        None => return None,
+    };
+    let coeff = expr.coeffs[solved];
+
+    loop {
+        // Candidates whose arithmetic overflows have no solution.
+        values[solved] = 0;
+        let rest = expr.eval(&values).and_then(|v| target.checked_sub(v));
+        if let Some(rest) = rest {
+            if rest.checked_rem(coeff) == Some(0) && ranges[solved].contains(&(rest / coeff)) {
+                values[solved] = rest / coeff;
+                return Some(values);
+            }
+        }
+
+        // Advance the symbols before `solved` like an odometer.
+        let mut i = solved;
+        loop {
+            if i == 0 {
+                return None;
+            }
+            i -= 1;
+            values[i] += 1;
+            if values[i] < ranges[i].end {
+                break;
+            }
+            values[i] = ranges[i].start;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_day2_example() {
+        let program = [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let analysis = analyze(&program, &[9, 10], &[], 100).unwrap();
+
+        let expr = analysis.memory[0].as_ref().unwrap();
+        assert_eq!(expr.to_string(), "0 + 50*x0 + 50*x1");
+        assert_eq!(expr.eval(&[30, 40]), Some(3500));
+        assert_eq!(solve(expr, 3500, &[0..100, 0..100]), Some(vec![0, 70]));
+    }
+
+    #[test]
+    fn test_unused_symbolic_read() {
+        // [3] = [x0] + [x1] is overwritten with x0 + x1 before anything uses it.
+        let program = [1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 7];
+        let analysis = analyze(&program, &[1, 2], &[], 100).unwrap();
+        assert_eq!(analysis.memory[0].as_ref().unwrap().coeffs, [7, 7]);
+
+        let program = [1, 0, 0, 5, 4, 5, 99];
+        let analysis = analyze(&program, &[1, 2], &[], 100);
+        assert_eq!(analysis, Err(Unsupported::SymbolicAddress { ip: 0 }));
+
+        let analysis = analyze(&program, &[1, 7], &[], 100);
+        assert_eq!(analysis, Err(Unsupported::SymbolOutOfBounds { addr: 7 }));
+    }
+
+    #[test]
+    fn test_outputs_and_constants() {
+        let program = [1002, 13, 3, 15, 1, 15, 14, 15, 4, 15, 104, 7, 99, 5, 0, 0];
+        let analysis = analyze(&program, &[13, 14], &[], 100).unwrap();
+
+        assert_eq!(analysis.output[0].coeffs, [3, 1]);
+        assert_eq!(analysis.output[1].as_constant(), Some(7));
+        assert_eq!(
+            solve(&analysis.output[0], 12, &[0..10, 0..1]),
+            Some(vec![4, 0])
+        );
+        assert_eq!(solve(&analysis.output[0], 13, &[0..10, 0..1]), None);
+        assert_eq!(
+            solve(&analysis.output[1], 7, &[3..10, 0..1]),
+            Some(vec![3, 0])
+        );
+    }
+
+    #[test]
+    fn test_relative_mode() {
+        // arb 10; [rb+0] = x0 * 4; out [rb+0]
+        let program = [109, 10, 21002, 11, 4, 0, 204, 0, 99, 0, 0, 0];
+        let analysis = analyze(&program, &[11], &[], 100).unwrap();
+        assert_eq!(analysis.output[0].coeffs, [4]);
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            analyze(&[2, 5, 5, 0, 99, 0], &[5], &[], 100),
+            Err(Unsupported::NonLinear { ip: 0 })
+        );
+        assert_eq!(
+            analyze(&[1, 5, 6, 0, 99, 0, 0], &[3], &[], 100),
+            Err(Unsupported::SymbolicAddress { ip: 0 })
+        );
+        assert_eq!(
+            analyze(&[1005, 4, 0, 99, 0], &[4], &[], 100),
+            Err(Unsupported::SymbolicBranch { ip: 0 })
+        );
+        assert_eq!(
+            analyze(&[1105, 1, 0], &[], &[], 100),
+            Err(Unsupported::StepLimit)
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let program = [1002, 9, 1073741824, 10, 1002, 10, 1073741824, 0, 99, 0, 0];
+        let analysis = analyze(&program, &[9], &[], 100).unwrap();
+
+        let expr = analysis.memory[0].as_ref().unwrap();
+        assert_eq!(expr.coeffs, [1 << 60]);
+        assert_eq!(expr.eval(&[7]), Some(7 << 60));
+        assert_eq!(expr.eval(&[8]), None);
+        let ranges = [Range { start: 0, end: 100 }];
+        assert_eq!(solve(expr, 4 << 60, &ranges), Some(vec![4]));
+
+        // x0 = 16 would wrap 16 * 2^60 around to 0 and "solve" this.
+        let expr = Affine {
+            constant: 0,
+            coeffs: vec![1 << 60, 1],
+        };
+        assert_eq!(solve(&expr, 5, &[1..100, 0..10]), None);
+        assert_eq!(solve(&expr, i64::MIN, &[0..100, -1..0]), None);
+    }
+}
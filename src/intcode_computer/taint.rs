@@ -0,0 +1,255 @@
+//! Dynamic taint tracking: which inputs each output and conditional jump
+//! depended on.
+//!
+//! Every value read by an input instruction is labelled with its index.
+//! Results carry the union of their operands' labels, and a value read
+//! through a pointer also carries the labels of whatever chose the address:
+//! the operand word itself for self-modifying code, and the relative base in
+//! relative mode. Everything an instruction produces also carries the labels
+//! of its opcode word, since a program like day 5's adds its input to an
+//! opcode to pick which instruction runs. Labels on the target address of a
+//! write are not carried into the written value.
+
+use super::trace::trace_step;
+use super::{Machine, OpCode, ParameterMode, State};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Indexes of the inputs a value depends on.
+pub type Labels = BTreeSet<usize>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaintedOutput {
+    pub index: usize,
+    pub value: i32,
+    pub ip: usize,
+    pub inputs: Labels,
+}
+
+/// Every execution of one conditional jump instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub ip: usize,
+    pub instruction: String,
+    pub taken: usize,
+    pub not_taken: usize,
+    /// Inputs that influenced the condition or target of any execution.
+    pub inputs: Labels,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The input values consumed, by index.
+    pub inputs: Vec<i32>,
+    pub outputs: Vec<TaintedOutput>,
+    /// Conditional jumps that ran, by address.
+    pub branches: Vec<Branch>,
+    /// Halted, or awaiting input once the queue ran out.
+    pub state: State,
+}
+
+struct Inputs<'a>(&'a Labels);
+
+impl fmt::Display for Inputs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "no inputs");
+        }
+        let labels: Vec<String> = self.0.iter().map(|i| i.to_string()).collect();
+        write!(f, "inputs {}", labels.join(","))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, value) in self.inputs.iter().enumerate() {
+            writeln!(f, "input {} = {}", index, value)?;
+        }
+        for output in &self.outputs {
+            writeln!(
+                f,
+                "output {} = {} at ip {} <- {}",
+                output.index,
+                output.value,
+                output.ip,
+                Inputs(&output.inputs)
+            )?;
+        }
+        for branch in &self.branches {
+            writeln!(
+                f,
+                "branch at ip {} ({}) taken {} of {} <- {}",
+                branch.ip,
+                branch.instruction,
+                branch.taken,
+                branch.taken + branch.not_taken,
+                Inputs(&branch.inputs)
+            )?;
+        }
+        write!(f, "{:?}", self.state)
+    }
+}
+
+/// Runs `machine` until it halts or runs out of input, tracking where every
+/// value came from.
+pub fn analyze(machine: &mut Machine) -> Result<Report, String> {
+    let mut shadow = vec![Labels::new(); machine.memory.len()];
+    let mut base = Labels::new();
+    let mut inputs = Vec::new();
+    let mut outputs = Vec::new();
+    let mut branches: BTreeMap<usize, Branch> = BTreeMap::new();
+
+    let state = loop {
+        let ip = machine.instr_ptr;
+        let op = machine.decode().ok().map(|decoded| {
+            let instr = &decoded.instr;
+            let mut reads = vec![shadow.get(ip).cloned().unwrap_or_default()];
+            let params =
+                (0..instr.modes.len()).filter(|&i| OpCode::write_param(instr.op) != Some(i));
+            for (i, &(_, addr)) in params.zip(&decoded.reads[..decoded.read_count]) {
+                let mut labels = shadow.get(ip + 1 + i).cloned().unwrap_or_default();
+                if let ParameterMode::Relative = instr.modes[i] {
+                    labels.extend(&base);
+                }
+                if let Some(addr) = addr {
+                    labels.extend(&shadow[addr]);
+                }
+                reads.push(labels);
+            }
+            (instr.op, reads)
+        });
+
+        let step = trace_step(machine);
+        match step.result {
+            Ok(State::Running) => {}
+            Ok(state) => break state,
+            Err(e) => return Err(e.to_string()),
+        }
+        let (op, reads) = match op {
+            Some(op) => op,
+            None => continue,
+        };
+        let union = || reads.iter().flatten().copied().collect::<Labels>();
+
+        match op {
+            OpCode::Input => {
+                if let Some((addr, value)) = step.write {
+                    shadow[addr] = union();
+                    shadow[addr].insert(inputs.len());
+                    inputs.push(value);
+                }
+            }
+            OpCode::Output => outputs.push(TaintedOutput {
+                index: outputs.len(),
+                value: *machine.output.last().unwrap(),
+                ip,
+                inputs: union(),
+            }),
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let branch = branches.entry(ip).or_insert_with(|| Branch {
+                    ip,
+                    instruction: step.instruction.clone(),
+                    taken: 0,
+                    not_taken: 0,
+                    inputs: Labels::new(),
+                });
+                if machine.instr_ptr == ip + OpCode::value_count(op) {
+                    branch.not_taken += 1;
+                } else {
+                    branch.taken += 1;
+                }
+                branch.inputs.extend(union());
+            }
+            OpCode::AdjustBase => base.extend(union()),
+            _ => {
+                if let Some((addr, _)) = step.write {
+                    shadow[addr] = union();
+                }
+            }
+        }
+    };
+
+    Ok(Report {
+        inputs,
+        outputs,
+        branches: branches.into_values().collect(),
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(inputs: &[usize]) -> Labels {
+        inputs.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_flow_through_memory() {
+        // Reads a and b, outputs a * 2, then a + b, then outputs 7 if b < 5.
+        let program = vec![
+            3, 30, 3, 31, 1002, 30, 2, 32, 4, 32, 1, 30, 31, 33, 4, 33, 1007, 31, 5, 34, 1006, 34,
+            25, 104, 7, 99, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut machine = Machine::new(program);
+        machine.push_input(10);
+        machine.push_input(3);
+        let report = analyze(&mut machine).unwrap();
+
+        assert_eq!(report.inputs, [10, 3]);
+        assert_eq!(report.state, State::Halted);
+        let outputs: Vec<(i32, Labels)> = report
+            .outputs
+            .iter()
+            .map(|output| (output.value, output.inputs.clone()))
+            .collect();
+        assert_eq!(
+            outputs,
+            [(20, labels(&[0])), (13, labels(&[0, 1])), (7, labels(&[]))]
+        );
+
+        assert_eq!(report.branches.len(), 1);
+        let branch = &report.branches[0];
+        assert_eq!((branch.ip, branch.taken, branch.not_taken), (20, 0, 1));
+        assert_eq!(branch.inputs, labels(&[1]));
+        assert_eq!(report.to_string().lines().last(), Some("Halted"));
+    }
+
+    #[test]
+    fn test_relative_base() {
+        // Moves the base by the input, then outputs the word it points at.
+        let mut machine = Machine::new(vec![3, 9, 209, 9, 204, 9, 99, 5, 6, 0]);
+        machine.push_input(-2);
+        let report = analyze(&mut machine).unwrap();
+        assert_eq!(report.outputs[0].value, 5);
+        assert_eq!(report.outputs[0].inputs, labels(&[0]));
+    }
+
+    #[test]
+    fn test_self_modifying_opcode() {
+        // Adds the input to the opcode at 6, making it an output of 7 (for 3)
+        // or a halt (for 98).
+        let mut machine = Machine::new(vec![3, 10, 1, 10, 6, 6, 101, 7, 99, 99, 0]);
+        machine.push_input(3);
+        let report = analyze(&mut machine).unwrap();
+        assert_eq!(report.outputs[0].value, 7);
+        assert_eq!(report.outputs[0].inputs, labels(&[0]));
+    }
+
+    #[test]
+    fn test_awaiting_input() {
+        let report = analyze(&mut Machine::new(vec![3, 0, 99])).unwrap();
+        assert_eq!(report.state, State::AwaitingInput);
+        assert!(analyze(&mut Machine::new(vec![42])).is_err());
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut machine = Machine::new(vec![3, 0, 1005, 0, 9]);
+        machine.push_input(1);
+        let error = analyze(&mut machine).unwrap_err();
+        assert_eq!(error, "address 9 is outside memory at ip 2");
+    }
+}
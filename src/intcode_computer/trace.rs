@@ -0,0 +1,272 @@
+use super::{Error, Machine, State};
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// What a single instruction did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub step: usize,
+    pub ip: usize,
+    pub instruction: String,
+    /// Values of the read parameters, followed by the target address of the
+    /// write parameter if there is one.
+    pub operands: Vec<i32>,
+    /// The `(address, value)` the instruction stored, if any.
+    pub write: Option<(usize, i32)>,
+    pub result: Result<State, Error>,
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>6} {:>5}: {:<24} {:?}",
+            self.step, self.ip, self.instruction, self.operands
+        )?;
+        if let Some((addr, value)) = self.write {
+            write!(f, " [{}] <- {}", addr, value)?;
+        }
+        match self.result {
+            Ok(State::Running) => Ok(()),
+            Ok(state) => write!(f, " ({:?})", state),
+            Err(e) => write!(f, " ({})", e),
+        }
+    }
+}
+
+/// Executes one instruction of `machine`, recording what it did.
+pub fn trace_step(machine: &mut Machine) -> TraceStep {
+    let ip = machine.instr_ptr;
+    let step = machine.steps;
+    let (instruction, _) = machine.memory.disassemble(ip);
+
+    let mut operands = Vec::new();
+    let mut write_ptr = None;
+    if let Ok(decoded) = machine.decode() {
+        operands.extend(decoded.reads[..decoded.read_count].iter().map(|&(v, _)| v));
+        if let Some(addr) = decoded.write {
+            write_ptr = Some(addr);
+            operands.push(addr as i32);
+        }
+    }
+
+    let result = machine.step();
+    let write = match result {
+        Ok(State::Running) => write_ptr.map(|addr| (addr, machine.memory[addr])),
+        _ => None,
+    };
+
+    TraceStep {
+        step,
+        ip,
+        instruction,
+        operands,
+        write,
+        result,
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Difference {
+    Ip,
+    Instruction,
+    Operands,
+    Write,
+    /// One machine stopped (halted, wanted input or failed) and the other
+    /// didn't, or they stopped differently.
+    Stopped,
+}
+
+/// Where two runs first disagreed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub step: usize,
+    pub difference: Difference,
+    /// The matching steps leading up to the divergence.
+    pub before: Vec<TraceStep>,
+    /// The divergent step and the ones after it, for each machine.
+    pub left: Vec<TraceStep>,
+    pub right: Vec<TraceStep>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "runs diverge at step {} ({:?} differs)",
+            self.step, self.difference
+        )?;
+        for step in &self.before {
+            writeln!(f, "  {}", step)?;
+        }
+        writeln!(f, "left:")?;
+        for step in &self.left {
+            writeln!(f, "  {}", step)?;
+        }
+        writeln!(f, "right:")?;
+        for step in &self.right {
+            writeln!(f, "  {}", step)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare(left: &TraceStep, right: &TraceStep) -> Option<Difference> {
+    if left.ip != right.ip {
+        Some(Difference::Ip)
+    } else if left.instruction != right.instruction {
+        Some(Difference::Instruction)
+    } else if left.operands != right.operands {
+        Some(Difference::Operands)
+    } else if left.write != right.write {
+        Some(Difference::Write)
+    } else if left.result != right.result {
+        Some(Difference::Stopped)
+    } else {
+        None
+    }
+}
+
+fn run_on(machine: &mut Machine, steps: usize, trace: &mut Vec<TraceStep>) {
+    while trace.len() < steps && trace.last().is_none_or(|s| s.result == Ok(State::Running)) {
+        trace.push(trace_step(machine));
+    }
+}
+
+/// Runs both machines in lock-step for up to `max_steps` instructions and
+/// reports the first step where they differ, with `context` steps either side.
+/// Returns `None` if they stop the same way or both reach the step limit.
+pub fn diff(
+    mut left: Machine,
+    mut right: Machine,
+    max_steps: usize,
+    context: usize,
+) -> Option<Divergence> {
+    let mut before = VecDeque::with_capacity(context + 1);
+
+    for _ in 0..max_steps {
+        let l = trace_step(&mut left);
+        let r = trace_step(&mut right);
+
+        if let Some(difference) = compare(&l, &r) {
+            let step = l.step;
+            let mut left_trace = vec![l];
+            let mut right_trace = vec![r];
+            run_on(&mut left, context + 1, &mut left_trace);
+            run_on(&mut right, context + 1, &mut right_trace);
+
+            return Some(Divergence {
+                step,
+                difference,
+                before: before.into_iter().collect(),
+                left: left_trace,
+                right: right_trace,
+            });
+        }
+
+        if l.result != Ok(State::Running) {
+            return None;
+        }
+
+        if context > 0 {
+            if before.len() == context {
+                before.pop_front();
+            }
+            before.push_back(l);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::ErrorKind;
+
+    #[test]
+    fn test_trace_step() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33]);
+        let step = trace_step(&mut machine);
+
+        assert_eq!(step.instruction, "mul [4], 3, [4]");
+        assert_eq!(step.operands, [33, 3, 4]);
+        assert_eq!(step.write, Some((4, 99)));
+        assert_eq!(step.result, Ok(State::Running));
+    }
+
+    #[test]
+    fn test_identical_runs() {
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let mut left = Machine::new(program.clone());
+        let mut right = Machine::new(program);
+        left.push_input(8);
+        right.push_input(8);
+
+        assert_eq!(diff(left, right, 1000, 2), None);
+    }
+
+    #[test]
+    fn test_different_inputs() {
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let mut left = Machine::new(program.clone());
+        let mut right = Machine::new(program);
+        left.push_input(8);
+        right.push_input(7);
+
+        let divergence = diff(left, right, 1000, 2).unwrap();
+        assert_eq!(divergence.step, 0);
+        assert_eq!(divergence.difference, Difference::Write);
+        assert!(divergence.before.is_empty());
+        assert_eq!(divergence.left.len(), 3);
+        assert_eq!(divergence.left[1].write, Some((9, 1)));
+        assert_eq!(divergence.right[1].write, Some((9, 0)));
+    }
+
+    #[test]
+    fn test_patched_program() {
+        let program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let mut patched = program.clone();
+        patched[2] = 13;
+
+        let divergence = diff(Machine::new(program), Machine::new(patched), 1000, 1).unwrap();
+        assert_eq!(divergence.step, 0);
+        assert_eq!(divergence.difference, Difference::Instruction);
+        assert_eq!(divergence.left[0].instruction, "add [0], [0], [3]");
+        assert_eq!(divergence.right[0].instruction, "add [0], [13], [3]");
+    }
+
+    #[test]
+    fn test_stopped() {
+        let left = Machine::new(vec![1101, 1, 1, 5, 99, 0]);
+        let right = Machine::new(vec![1101, 1, 1, 5, 3, 0, 99]);
+
+        let divergence = diff(left, right, 1000, 3).unwrap();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.difference, Difference::Instruction);
+        assert_eq!(divergence.before.len(), 1);
+        assert_eq!(divergence.left[0].result, Ok(State::Halted));
+        assert_eq!(divergence.right[0].result, Ok(State::AwaitingInput));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut machine = Machine::new(vec![1101, 1, 1, 0]);
+        trace_step(&mut machine);
+        let step = trace_step(&mut machine);
+        assert_eq!(step.instruction, "<out of bounds>");
+        assert!(step.operands.is_empty());
+        assert_eq!(step.result.unwrap_err().kind, ErrorKind::OutOfBounds(4));
+
+        let left = Machine::new(vec![1, 0, 0, 0, 99]);
+        let right = Machine::new(vec![1, 100, 0, 0, 99]);
+        let divergence = diff(left, right, 1000, 1).unwrap();
+        assert_eq!(divergence.difference, Difference::Instruction);
+        assert!(divergence.right[0].operands.is_empty());
+        assert_eq!(
+            divergence.right[0].to_string(),
+            "     0     0: add [100], [0], [0]      [] (address 100 is outside memory at ip 0)"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use super::flow;
+use super::{Instruction, OpCode, ParameterMode};
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+/// Why compiled code handed control back to its caller, and the ip to resume at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exit {
+    Halted(usize),
+    AwaitingInput(usize),
+    /// The instruction at this ip couldn't be compiled (it may have been
+    /// modified at run time) or overflowed, so the interpreter has to take over.
+    Fallback(usize),
+}
+
+/// The signature of functions generated by `transpile`: memory, input queue,
+/// output and the ip to start at.
+pub type Compiled =
+    fn(&mut [i32], &mut std::collections::VecDeque<i32>, &mut Vec<i32>, usize) -> Exit;
+
+/// Translates `program` into the source of a Rust function called `name`, with
+/// the `Compiled` signature, to be run with `Machine::run_compiled`.
+///
+/// Code is found the way the control flow graph finds it, following
+/// fall-through and immediate jump targets from address 0. Every instruction becomes one arm of a match on the ip, with
+/// operands baked in as constants unless some instruction writes to them.
+/// Instructions that are themselves written to, instructions whose write
+/// address is written to and instructions with addresses outside `program`
+/// compile to
+/// `Exit::Fallback`, as does any jump into an address that wasn't reached
+/// statically. Relative mode and `arb` aren't compiled, and since relative
+/// writes could land anywhere, a program with any of them compiles to nothing
+/// but fallbacks.
+///
+/// Everything baked in is checked against `mem` on entry, so a machine whose
+/// memory no longer matches `program`, say after `Machine::reset_with`, falls
+/// straight back to the interpreter.
+///
+/// The generated code expects `Exit` and `VecDeque` to be in scope.
+pub fn transpile(program: &[i32], name: &str) -> String {
+    let (code, _) = flow::find_code(program, &[0], &BTreeSet::new());
+
+    let written: HashSet<usize> = code
+        .iter()
+        .filter_map(|(&ip, instr)| {
+            OpCode::write_param(instr.op).map(|param| program[ip + 1 + param] as usize)
+        })
+        .collect();
+
+    let relative_writes = code.values().any(|instr| {
+        OpCode::write_param(instr.op)
+            .is_some_and(|p| matches!(instr.modes[p], ParameterMode::Relative))
+    });
+
+    let mut arms = String::new();
+    let mut baked = Vec::new();
+    for (&ip, instr) in &code {
+        let modified = relative_writes
+            || written.contains(&ip)
+            || OpCode::write_param(instr.op).is_some_and(|p| written.contains(&(ip + 1 + p)));
+        let arm = match compile_instruction(program, ip, instr, &written) {
+            Some(arm) if !modified => {
+                let words = ip..ip + OpCode::value_count(instr.op);
+                baked.extend(words.filter(|addr| !written.contains(addr)));
+                arm
+            }
+            _ => format!("return Exit::Fallback({})", ip),
+        };
+        writeln!(arms, "            {} => {{ {} }}", ip, arm).unwrap();
+    }
+
+    let mut source = String::new();
+    writeln!(
+        source,
+        "#[allow(unused_variables, unused_parens)]\npub fn {}(mem: &mut [i32], input: &mut VecDeque<i32>, output: &mut Vec<i32>, ip: usize) -> Exit {{",
+        name
+    )
+    .unwrap();
+    let baked: Vec<String> = baked
+        .iter()
+        .map(|&addr| format!("({}, {})", addr, literal(program[addr])))
+        .collect();
+    writeln!(
+        source,
+        "    const BAKED: &[(usize, i32)] = &[{}];\n    \
+         if mem.len() != {} || BAKED.iter().any(|&(addr, word)| mem[addr] != word) {{\n        \
+         return Exit::Fallback(ip);\n    }}",
+        baked.join(", "),
+        program.len()
+    )
+    .unwrap();
+    source.push_str("    let mut ip = ip;\n    loop {\n        ip = match ip {\n");
+    source.push_str(&arms);
+
+    source.push_str("            _ => return Exit::Fallback(ip),\n        };\n    }\n}\n");
+    source
+}
+
+fn literal(value: i32) -> String {
+    if value < 0 {
+        format!("({}i32)", value)
+    } else {
+        format!("{}i32", value)
+    }
+}
+
+/// Returns `None` if the instruction has to be left to the interpreter.
+fn compile_instruction(
+    program: &[i32],
+    ip: usize,
+    instr: &Instruction,
+    written: &HashSet<usize>,
+) -> Option<String> {
+    let in_bounds = |addr: i32| addr >= 0 && (addr as usize) < program.len();
+    let mut operands = Vec::new();
+    for (i, &mode) in instr.modes.iter().enumerate() {
+        let addr = ip + 1 + i;
+        operands.push(match mode {
+            ParameterMode::Immediate if written.contains(&addr) => format!("mem[{}]", addr),
+            ParameterMode::Immediate => literal(program[addr]),
+            ParameterMode::Position if written.contains(&addr) => format!(
+                "match mem.get(mem[{}] as usize) {{ Some(&v) => v, None => return Exit::Fallback({}) }}",
+                addr, ip
+            ),
+            ParameterMode::Position if !in_bounds(program[addr]) => return None,
+            ParameterMode::Position => format!("mem[{}]", program[addr]),
+            ParameterMode::Relative => return None,
+        });
+    }
+    if OpCode::write_param(instr.op).is_some_and(|p| !in_bounds(program[ip + 1 + p])) {
+        return None;
+    }
+    let target = |i: usize| program[ip + 1 + i];
+    let next = ip + OpCode::value_count(instr.op);
+
+    Some(match instr.op {
+        OpCode::Add | OpCode::Mul => format!(
+            "match {}.checked_{}({}) {{ Some(v) => mem[{}] = v, None => return Exit::Fallback({}) }} {}",
+            operands[0],
+            if let OpCode::Add = instr.op { "add" } else { "mul" },
+            operands[1],
+            target(2),
+            ip,
+            next
+        ),
+        OpCode::LessThan | OpCode::Equals => format!(
+            "mem[{}] = ({} {} {}) as i32; {}",
+            target(2),
+            operands[0],
+            if let OpCode::LessThan = instr.op { "<" } else { "==" },
+            operands[1],
+            next
+        ),
+        OpCode::Input => format!(
+            "match input.pop_front() {{ Some(v) => mem[{}] = v, None => return Exit::AwaitingInput({}) }} {}",
+            target(0),
+            ip,
+            next
+        ),
+        OpCode::Output => format!("output.push({}); {}", operands[0], next),
+        OpCode::JumpIfTrue | OpCode::JumpIfFalse => format!(
+            "if {} {} 0 {{ {} as usize }} else {{ {} }}",
+            operands[0],
+            if let OpCode::JumpIfTrue = instr.op { "!=" } else { "==" },
+            operands[1],
+            next
+        ),
+        OpCode::AdjustBase => return None,
+        OpCode::Halt => format!("return Exit::Halted({})", ip),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode_computer::tests::summer;
+    use crate::intcode_computer::{Machine, State};
+
+    use std::collections::VecDeque;
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    #[test]
+    fn test_self_modifying_fallback() {
+        let source = transpile(&[1101, 100, -1, 4, 0], "f");
+        assert!(
+            source.contains("0 => { match 100i32.checked_add((-1i32)) { Some(v) => mem[4] = v,")
+        );
+        assert!(!source.contains("\n            4 =>"));
+
+        let source = transpile(&[1, 0, 0, 0, 99], "f");
+        assert!(source.contains("0 => { return Exit::Fallback(0) }"));
+
+        let source = transpile(&[104, 1, 21101, 1, 1, 0, 99], "f");
+        assert!(source.contains("0 => { return Exit::Fallback(0) }"));
+        assert!(source.contains("2 => { return Exit::Fallback(2) }"));
+    }
+
+    #[test]
+    fn test_run_compiled() {
+        fn compiled(
+            mem: &mut [i32],
+            input: &mut VecDeque<i32>,
+            _output: &mut Vec<i32>,
+            ip: usize,
+        ) -> Exit {
+            match ip {
+                0 => match input.pop_front() {
+                    Some(v) => {
+                        mem[5] = v;
+                        Exit::Fallback(2)
+                    }
+                    None => Exit::AwaitingInput(0),
+                },
+                _ => Exit::Fallback(ip),
+            }
+        }
+
+        let mut machine = Machine::new(vec![3, 5, 4, 5, 99, 0]);
+        assert_eq!(machine.run_compiled(compiled), Ok(State::AwaitingInput));
+        machine.push_input(42);
+        assert_eq!(machine.run_compiled(compiled), Ok(State::Halted));
+        assert_eq!(machine.output(), [42]);
+    }
+
+    // Adds [1] and [2] into [9], then doubles it into [10].
+    const ADDER: [i32; 11] = [1101, 0, 0, 9, 1, 9, 9, 10, 99, 0, 0];
+
+    #[test]
+    fn test_run_compiled_after_patching() {
+        fn compiled(mem: &mut [i32], _: &mut VecDeque<i32>, _: &mut Vec<i32>, ip: usize) -> Exit {
+            // What `transpile(&ADDER, ..)` generates, minus the entry check.
+            if mem[1..3] != [0, 0] {
+                return Exit::Fallback(ip);
+            }
+            mem[9] = 0;
+            mem[10] = 0;
+            Exit::Halted(8)
+        }
+
+        let mut machine = Machine::new(&ADDER);
+        machine.reset_with(&[(1, 3), (2, 4)]).unwrap();
+        assert_eq!(machine.run_compiled(compiled), Ok(State::Halted));
+        assert_eq!(machine.memory()[10], 14);
+        assert!(transpile(&ADDER, "f").contains("(1, 0i32), (2, 0i32)"));
+
+        fn unreachable(_: &mut [i32], _: &mut VecDeque<i32>, _: &mut Vec<i32>, _: usize) -> Exit {
+            panic!("strict machines interpret")
+        }
+        let mut machine = Machine::new(vec![11101, 1, 1, 0, 99]).strict(true);
+        assert!(machine.run_compiled(unreachable).is_err());
+    }
+
+    #[test]
+    fn test_generated_code_compiles_and_matches() {
+        let dir = env::temp_dir().join(format!("intcode-transpile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let source = format!(
+            "use std::collections::VecDeque;\n\
+             #[derive(Debug)] pub enum Exit {{ Halted(usize), AwaitingInput(usize), Fallback(usize) }}\n\
+             {}\n\
+             fn main() {{\n\
+                 let mut mem = vec!{:?};\n\
+                 let mut input: VecDeque<i32> = std::env::args().skip(1).map(|a| a.parse().unwrap()).collect();\n\
+                 let mut output = Vec::new();\n\
+                 let exit = summer(&mut mem, &mut input, &mut output, 0);\n\
+                 println!(\"{{:?}} {{:?}}\", exit, output);\n\
+                 for patches in [&[][..], &[(1, 3), (2, 4)]] {{\n\
+                     let mut mem = vec!{:?};\n\
+                     for &(addr, value) in patches {{ mem[addr] = value; }}\n\
+                     println!(\"{{:?}} {{}}\", adder(&mut mem, &mut input, &mut output, 0), mem[10]);\n\
+                 }}\n\
+             }}\n{}",
+            transpile(&summer(), "summer"),
+            summer(),
+            ADDER,
+            transpile(&ADDER, "adder"),
+        );
+        fs::write(dir.join("summer.rs"), source).unwrap();
+
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let status = Command::new(rustc)
+            .args([
+                "--edition",
+                "2018",
+                "-O",
+                "-D",
+                "unused-variables",
+                "-D",
+                "unused-parens",
+            ])
+            .args(["summer.rs", "-o", "summer"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(dir.join("summer")).arg("10").output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Halted(22) [55]\nHalted(8) 0\nFallback(0) 0\n"
+        );
+
+        let mut machine = Machine::new(summer());
+        machine.push_input(10);
+        machine.run().unwrap();
+        assert_eq!(machine.output(), [55]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
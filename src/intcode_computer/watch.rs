@@ -0,0 +1,254 @@
+//! A full-screen view of a running machine, drawn with ANSI escapes: the
+//! disassembly around the ip, the I/O queues and counters, and a hex grid of
+//! memory with recent writes highlighted. `intcode-watch` drives it from
+//! the keyboard.
+
+use super::trace::trace_step;
+use super::{Error, Machine, State};
+
+use std::collections::VecDeque;
+
+const HISTORY: usize = 3;
+const RECENT_WRITES: usize = 8;
+
+const RESET: &str = "\x1b[0m";
+const REVERSE: &str = "\x1b[7m";
+const NEWEST: &str = "\x1b[1;33m";
+const RECENT: &str = "\x1b[33m";
+
+/// A machine plus what the view remembers about its recent past.
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub machine: Machine,
+    /// Addresses of the last few instructions run, oldest first.
+    history: VecDeque<usize>,
+    /// Addresses of the last few writes, oldest first.
+    writes: VecDeque<usize>,
+    outputs: Vec<i32>,
+    last: Result<State, Error>,
+}
+
+impl Watch {
+    pub fn new(machine: Machine) -> Self {
+        Watch {
+            machine,
+            history: VecDeque::new(),
+            writes: VecDeque::new(),
+            outputs: Vec::new(),
+            last: Ok(State::Running),
+        }
+    }
+
+    /// What the last step returned.
+    pub fn last(&self) -> Result<State, Error> {
+        self.last
+    }
+
+    pub fn outputs(&self) -> &[i32] {
+        &self.outputs
+    }
+
+    pub fn step(&mut self) -> Result<State, Error> {
+        let step = trace_step(&mut self.machine);
+        if let Ok(State::Running) = step.result {
+            if self.history.len() == HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(step.ip);
+        }
+        if let Some((addr, _)) = step.write {
+            self.writes.retain(|&a| a != addr);
+            if self.writes.len() == RECENT_WRITES {
+                self.writes.pop_front();
+            }
+            self.writes.push_back(addr);
+        }
+        self.outputs.extend(self.machine.take_output());
+        self.last = step.result;
+        step.result
+    }
+
+    /// Steps up to `steps` times, stopping early if the machine stops.
+    pub fn run(&mut self, steps: usize) -> Result<State, Error> {
+        for _ in 0..steps {
+            match self.step()? {
+                State::Running => {}
+                state => return Ok(state),
+            }
+        }
+        Ok(State::Running)
+    }
+
+    /// Draws a `width` by `height` screen from the top left corner, with
+    /// `status` on the bottom line.
+    pub fn frame(&self, width: usize, height: usize, status: &str) -> String {
+        let machine = &self.machine;
+        let state = match self.last {
+            Ok(State::Running) => String::new(),
+            Ok(State::AwaitingInput) => "awaiting input".to_string(),
+            Ok(State::Halted) => "halted".to_string(),
+            Err(e) => e.to_string(),
+        };
+        let mut lines = vec![format!(
+            "{}{}{}",
+            REVERSE,
+            fit(
+                &format!(
+                    " intcode  step {}  ip {}  rb {}  {}",
+                    machine.steps, machine.instr_ptr, machine.relative_base, state
+                ),
+                width
+            ),
+            RESET
+        )];
+
+        let pane = height.saturating_sub(3) / 2;
+        let left = width / 2;
+        let code = self.disassembly(pane, left);
+        let io = self.io(pane, width - left);
+        for (code, io) in code.into_iter().zip(io) {
+            lines.push(format!("{}{}", code, io));
+        }
+
+        lines.push(fit("", width).replace(' ', "-"));
+        let rows = height.saturating_sub(lines.len() + 1);
+        lines.extend(self.memory(rows, width));
+        lines.resize(height.saturating_sub(1), String::new());
+        lines.push(fit(status, width));
+
+        format!("\x1b[H{}\x1b[J", lines.join("\x1b[K\r\n"))
+    }
+
+    /// Recently run instructions, then the one at the ip and the ones after
+    /// it in memory.
+    fn disassembly(&self, rows: usize, width: usize) -> Vec<String> {
+        let memory = &self.machine.memory;
+        let ip = self.machine.instr_ptr;
+        let mut lines = Vec::new();
+
+        for &addr in self.history.iter().filter(|&&addr| addr != ip) {
+            let (text, _) = memory.disassemble(addr);
+            lines.push(fit(&format!("  {:>5}: {}", addr, text), width));
+        }
+        let mut addr = ip;
+        while lines.len() < rows && addr < memory.len() {
+            let (text, len) = memory.disassemble(addr);
+            let line = fit(&format!("> {:>5}: {}", addr, text), width);
+            if addr == ip {
+                lines.push(format!("{}{}{}", REVERSE, line, RESET));
+            } else {
+                lines.push(line.replacen('>', " ", 1));
+            }
+            addr += len;
+        }
+
+        let skip = lines.len().saturating_sub(rows);
+        lines.drain(..skip);
+        lines.resize(rows, fit("", width));
+        lines
+    }
+
+    fn io(&self, rows: usize, width: usize) -> Vec<String> {
+        let input: Vec<String> = self.machine.input.iter().map(|v| v.to_string()).collect();
+        let mut lines = vec![
+            format!("input: {}", input.join(",")),
+            format!("outputs: {}", self.outputs.len()),
+        ];
+        let shown = rows.saturating_sub(lines.len());
+        let start = self.outputs.len().saturating_sub(shown);
+        lines.extend(self.outputs[start..].iter().map(|v| format!("  {}", v)));
+
+        lines.resize(rows, String::new());
+        lines.iter().map(|line| fit(line, width)).collect()
+    }
+
+    /// Rows of memory around the latest write, or the ip if nothing has been
+    /// written yet.
+    fn memory(&self, rows: usize, width: usize) -> Vec<String> {
+        let memory = &self.machine.memory;
+        let columns = (width.saturating_sub(6) / 9).max(1);
+        let focus = self
+            .writes
+            .back()
+            .copied()
+            .unwrap_or(self.machine.instr_ptr);
+        let total = memory.len().div_ceil(columns);
+        let first = (focus / columns)
+            .saturating_sub(rows / 2)
+            .min(total.saturating_sub(rows));
+
+        (first..total.min(first + rows))
+            .map(|row| {
+                let mut line = format!("{:04x}:", row * columns);
+                for addr in row * columns..memory.len().min((row + 1) * columns) {
+                    let cell = format!(" {:08x}", memory[addr]);
+                    let colour = if self.writes.back() == Some(&addr) {
+                        NEWEST
+                    } else if self.writes.contains(&addr) {
+                        RECENT
+                    } else if addr == self.machine.instr_ptr {
+                        REVERSE
+                    } else {
+                        line.push_str(&cell);
+                        continue;
+                    };
+                    line.push_str(&format!(" {}{}{}", colour, &cell[1..], RESET));
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+/// Pads or cuts `text` to exactly `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    format!("{:<width$.width$}", text, width = width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watch() -> Watch {
+        let mut machine = Machine::new(vec![3, 9, 1001, 9, -1, 10, 4, 10, 99, 0, 0]);
+        machine.push_input(5);
+        Watch::new(machine)
+    }
+
+    #[test]
+    fn test_step_records_writes_and_outputs() {
+        let mut watch = watch();
+        assert_eq!(watch.run(2), Ok(State::Running));
+        assert_eq!(watch.writes, [9, 10]);
+        assert_eq!(watch.history, [0, 2]);
+
+        assert_eq!(watch.run(10), Ok(State::Halted));
+        assert_eq!(watch.last(), Ok(State::Halted));
+        assert_eq!(watch.outputs(), [4]);
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut watch = watch();
+        watch.run(2).unwrap();
+        let frame = watch.frame(80, 12, "paused");
+        let lines: Vec<&str> = frame.split("\x1b[K\r\n").collect();
+
+        assert_eq!(lines.len(), 12);
+        assert!(lines[0].contains("step 2  ip 6  rb 0"));
+        assert!(lines[1].starts_with("      0: in [9]"));
+        assert!(lines[3].starts_with(&format!("{}>     6: out [10]", REVERSE)));
+        assert!(lines[1].contains("input: "));
+        // The latest write stands out from the one before it.
+        assert!(frame.contains(&format!("{}00000005{}", RECENT, RESET)));
+        assert!(frame.contains(&format!("{}00000004{}", NEWEST, RESET)));
+        assert!(lines[11].starts_with("paused"));
+        assert!(lines[11].ends_with("\x1b[J"));
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcdef", 3), "abc");
+    }
+}
@@ -10,11 +10,13 @@ macro_rules! day {
 mod utils;
 use utils::Part;
 
-mod intcode_computer;
+use aoc2019::intcode_computer;
 
 mod day1;
 mod day2;
+#[allow(clippy::clone_on_copy)]
 mod day3;
+#[allow(clippy::bool_assert_comparison, non_fmt_panics)]
 mod day4;
 mod day5;
 
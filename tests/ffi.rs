@@ -0,0 +1,38 @@
+//! Builds `tests/ffi/test.c` against the crate's cdylib and runs it.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory holding `libaoc2019.so`, next to this test's executable.
+fn library_dir() -> PathBuf {
+    env::current_exe().unwrap().parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn test_c_program() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi-test");
+    let lib = library_dir();
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(root.join("tests/ffi/test.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib)
+        .args(["-laoc2019", "-Wall", "-Werror", "-o"])
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(&out)
+        .env("LD_LIBRARY_PATH", &lib)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+    assert!(output.status.success());
+}
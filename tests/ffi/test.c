@@ -0,0 +1,65 @@
+/* Exercises the C interface; built and run by tests/ffi.rs. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "intcode.h"
+
+#define CHECK(cond)                                                      \
+    do {                                                                 \
+        if (!(cond)) {                                                   \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,       \
+                    __LINE__, #cond);                                    \
+            return 1;                                                    \
+        }                                                                \
+    } while (0)
+
+int main(void) {
+    /* Outputs 1 if the input equals 8, else 0 (day 5 example). */
+    const int32_t program[] = {3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8};
+    const size_t len = sizeof(program) / sizeof(program[0]);
+    int32_t value;
+
+    IntcodeMachine *machine = intcode_new(program, len);
+    CHECK(machine != NULL);
+    CHECK(intcode_memory_len(machine) == len);
+
+    CHECK(intcode_run(machine) == INTCODE_AWAITING_INPUT);
+    intcode_push_input(machine, 8);
+    CHECK(intcode_run(machine) == INTCODE_OUTPUT);
+    CHECK(intcode_pop_output(machine, &value) == 1 && value == 1);
+    CHECK(intcode_pop_output(machine, &value) == 0);
+    CHECK(intcode_run(machine) == INTCODE_HALTED);
+    CHECK(intcode_error(machine) == NULL);
+
+    /* The comparison result is stored in [9] before being output. */
+    CHECK(intcode_read(machine, 9, &value) == 1 && value == 1);
+    CHECK(intcode_read(machine, len, &value) == 0);
+
+    /* Patch the first instruction into an unknown opcode and rerun. */
+    intcode_free(machine);
+    machine = intcode_new(program, len);
+    CHECK(intcode_write(machine, 0, 42) == 1);
+    CHECK(intcode_run(machine) == INTCODE_ERROR);
+    CHECK(strcmp(intcode_error(machine),
+                 "1202 program error: unknown opcode 42 at ip 0") == 0);
+    intcode_free(machine);
+
+    /* Operands outside memory are errors, not crashes. */
+    const int32_t out_of_bounds[] = {1, 100, 0, 0, 99};
+    machine = intcode_new(out_of_bounds, 5);
+    CHECK(intcode_run(machine) == INTCODE_ERROR);
+    CHECK(strcmp(intcode_error(machine),
+                 "address 100 is outside memory at ip 0") == 0);
+    intcode_free(machine);
+
+    machine = intcode_new(NULL, 0);
+    CHECK(intcode_run(machine) == INTCODE_ERROR);
+    intcode_free(machine);
+
+    CHECK(intcode_new(NULL, 3) == NULL);
+    intcode_free(NULL);
+
+    printf("ok\n");
+    return 0;
+}